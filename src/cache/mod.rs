@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{event, Level};
+
+mod remote;
+
+pub use remote::{RemoteCache, RemoteMode};
+
+const DEFAULT_MAX_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+// Content-addressed store for build outputs, shared between checkouts. Entries are
+// touched on every hit so eviction can drop the least recently used ones first.
+#[derive(Debug, Clone)]
+pub struct ObjectCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl ObjectCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        return Self {
+            dir: dir.into(),
+            max_size: DEFAULT_MAX_SIZE,
+        };
+    }
+
+    // `$CBUILD_CACHE_DIR`, or `cbuild` in the user's cache directory.
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("CBUILD_CACHE_DIR") {
+            return dir.into();
+        }
+        if let Some(dir) = std::env::var_os("XDG_CACHE_HOME") {
+            return PathBuf::from(dir).join("cbuild");
+        }
+        if let Some(dir) = std::env::var_os("LOCALAPPDATA") {
+            return PathBuf::from(dir).join("cbuild");
+        }
+        if let Some(home) = std::env::var_os("HOME") {
+            return PathBuf::from(home).join(".cache").join("cbuild");
+        }
+        return std::env::temp_dir().join("cbuild-cache");
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        return self;
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry(&self, key: &str) -> PathBuf {
+        let shard = key.get(..2).unwrap_or(key);
+        return self.dir.join(shard).join(key);
+    }
+
+    // Copies the entry for `key` to `dest`, returning whether there was one.
+    pub fn get(&self, key: &str, dest: &Path) -> bool {
+        let entry = self.entry(key);
+        if !entry.is_file() {
+            return false;
+        }
+        if let Err(e) = std::fs::copy(&entry, dest) {
+            event!(
+                Level::WARN,
+                "failed to restore `{}` from cache: `{}`",
+                dest.display(),
+                e
+            );
+            return false;
+        }
+        let touched = std::fs::File::options()
+            .write(true)
+            .open(&entry)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(e) = touched {
+            event!(Level::DEBUG, "failed to touch cache entry: `{}`", e);
+        }
+        return true;
+    }
+
+    pub fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        return std::fs::read(self.entry(key)).ok();
+    }
+
+    pub fn put(&self, key: &str, src: &Path) {
+        match std::fs::read(src) {
+            Ok(data) => self.put_bytes(key, &data),
+            Err(e) => event!(
+                Level::WARN,
+                "failed to read `{}` for caching: `{}`",
+                src.display(),
+                e
+            ),
+        }
+    }
+
+    // Doesn't evict: a scan of the whole cache per entry would make a build quadratic,
+    // so builds call `evict` once they are done storing.
+    pub fn put_bytes(&self, key: &str, data: &[u8]) {
+        let entry = self.entry(key);
+        let tmp = entry.with_extension(format!("tmp-{}", std::process::id()));
+        // Write then rename, so concurrent builds never observe a partial entry.
+        let stored = std::fs::create_dir_all(entry.parent().unwrap_or(&self.dir))
+            .and_then(|_| std::fs::write(&tmp, data))
+            .and_then(|_| std::fs::rename(&tmp, &entry));
+        if let Err(e) = stored {
+            event!(Level::WARN, "failed to store cache entry: `{}`", e);
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+
+    fn entries(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let mut entries = Vec::new();
+        let Ok(shards) = self.dir.read_dir() else {
+            return entries;
+        };
+        for shard in shards.flatten() {
+            let Ok(files) = shard.path().read_dir() else {
+                continue;
+            };
+            for file in files.flatten() {
+                if let Ok(metadata) = file.metadata() {
+                    let time = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    entries.push((file.path(), metadata.len(), time));
+                }
+            }
+        }
+        entries
+    }
+
+    pub fn size(&self) -> u64 {
+        return self.entries().iter().map(|(_, size, _)| size).sum();
+    }
+
+    // Removes least recently used entries until the cache fits in `max_size`.
+    pub fn evict(&self) {
+        let mut entries = self.entries();
+        let mut size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        if size <= self.max_size {
+            return;
+        }
+        entries.sort_by_key(|(_, _, time)| *time);
+        for (path, len, _) in entries {
+            if size <= self.max_size {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                size -= len;
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{event, Level};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteMode {
+    ReadOnly,
+    ReadWrite,
+}
+
+// Cache entries stored as `<url>/<key>` on a plain HTTP server: `GET` to fetch,
+// `PUT` to upload. The first network failure disables the remote for the rest of
+// the build so an unreachable server only costs one timeout.
+#[derive(Debug, Clone)]
+pub struct RemoteCache {
+    host: String,
+    port: u16,
+    prefix: String,
+    mode: RemoteMode,
+    timeout: Duration,
+    offline: Arc<AtomicBool>,
+}
+
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl RemoteCache {
+    // Accepts `http://host[:port][/prefix]`, with IPv6 hosts in brackets: `http://[::1]:8080`.
+    pub fn new(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, prefix) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, port) = bracketed.split_once(']')?;
+                match port {
+                    "" => (host, 80),
+                    port => (host, port.strip_prefix(':')?.parse().ok()?),
+                }
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, port.parse().ok()?),
+                None => (authority, 80),
+            },
+        };
+        if host.is_empty() {
+            return None;
+        }
+        return Some(Self {
+            host: host.to_string(),
+            port,
+            prefix: prefix.to_string(),
+            mode: RemoteMode::ReadWrite,
+            timeout: Duration::from_secs(5),
+            offline: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    pub fn mode(mut self, mode: RemoteMode) -> Self {
+        self.mode = mode;
+        return self;
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    pub fn is_writable(&self) -> bool {
+        return self.mode == RemoteMode::ReadWrite;
+    }
+
+    pub fn is_offline(&self) -> bool {
+        return self.offline.load(Ordering::Relaxed);
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let response = self.request("GET", key, &[])?;
+        match response.status {
+            200 => return Some(response.body),
+            404 => return None,
+            status => {
+                event!(
+                    Level::WARN,
+                    "remote cache returned {} for `{}`",
+                    status,
+                    key
+                );
+                return None;
+            }
+        }
+    }
+
+    pub fn put(&self, key: &str, data: &[u8]) -> bool {
+        if !self.is_writable() {
+            return false;
+        }
+        let Some(response) = self.request("PUT", key, data) else {
+            return false;
+        };
+        if !(200..300).contains(&response.status) {
+            event!(
+                Level::WARN,
+                "remote cache rejected `{}` with {}",
+                key,
+                response.status
+            );
+            return false;
+        }
+        return true;
+    }
+
+    fn request(&self, method: &str, key: &str, body: &[u8]) -> Option<Response> {
+        if self.is_offline() {
+            return None;
+        }
+        match self.send(method, key, body) {
+            Ok(response) => return Some(response),
+            Err(e) => {
+                event!(
+                    Level::WARN,
+                    "remote cache `{}` unavailable, building locally: `{}`",
+                    self.authority(),
+                    e
+                );
+                self.offline.store(true, Ordering::Relaxed);
+                return None;
+            }
+        }
+    }
+
+    fn authority(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port),
+            false => format!("{}:{}", self.host, self.port),
+        }
+    }
+
+    fn send(&self, method: &str, key: &str, body: &[u8]) -> std::io::Result<Response> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::other("host did not resolve"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let head = format!(
+            "{} {}/{} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            method,
+            self.prefix,
+            key,
+            self.authority(),
+            body.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw)?;
+        return parse_response(&raw).ok_or_else(|| std::io::Error::other("malformed response"));
+    }
+}
+
+fn parse_response(raw: &[u8]) -> Option<Response> {
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..split]).ok()?;
+    let body = &raw[split + 4..];
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+
+    let mut length = None;
+    let mut chunked = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse::<usize>().ok();
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        match length {
+            Some(length) => body.get(..length)?.to_vec(),
+            None => body.to_vec(),
+        }
+    };
+    return Some(Response { status, body });
+}
+
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
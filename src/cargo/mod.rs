@@ -0,0 +1,290 @@
+use crate::compiler::{
+    CompileCommand, CompileCommandBuilder, Compiler, DebugInfo, OptimizationLevel, Status,
+};
+use crate::launcher::Launcher;
+use crate::linker::{BinType, Linker, LinkerCommand};
+use crate::toolchain::{Family, ToolchainInfo, Version};
+use std::path::PathBuf;
+use tracing::{event, Level};
+
+// Settings Cargo passes to `build.rs` through the environment.
+#[derive(Debug, Clone)]
+pub struct BuildScript {
+    out_dir: PathBuf,
+    manifest_dir: PathBuf,
+    target: String,
+    profile: String,
+    opt_level: Option<String>,
+    debug: Option<String>,
+    cc: Option<String>,
+    cc_launcher: Option<Launcher>,
+    cc_args: Vec<String>,
+    cflags: Vec<String>,
+    ar: Option<String>,
+}
+
+// Looked up like the `cc` crate does: `<name>_<target>`, `<name>_<target_with_underscores>`,
+// `TARGET_<name>`, then `<name>`.
+fn env_names(name: &str, target: &str) -> [String; 4] {
+    return [
+        format!("{}_{}", name, target),
+        format!("{}_{}", name, target.replace('-', "_")),
+        format!("TARGET_{}", name),
+        name.to_string(),
+    ];
+}
+
+// `CC` can carry a compiler cache in front of the compiler (`ccache gcc`) and
+// arguments after it (`gcc -m32`).
+fn split_cc(cc: &str) -> Option<(Option<Launcher>, String, Vec<String>)> {
+    let mut words = cc.split_whitespace().map(String::from);
+    let first = words.next()?;
+    let stem = first.rsplit(['/', '\\']).next().unwrap_or(&first);
+    let launcher = match stem.strip_suffix(".exe").unwrap_or(stem) {
+        "ccache" => Some(Launcher::Ccache),
+        "sccache" => Some(Launcher::Sccache),
+        "distcc" | "icecc" | "buildcache" => Some(Launcher::Custom(first.clone().into())),
+        _ => None,
+    };
+    let (launcher, program) = match launcher {
+        Some(launcher) => (Some(launcher), words.next()?),
+        None => (None, first),
+    };
+    return Some((launcher, program, words.collect()));
+}
+
+impl BuildScript {
+    // `None` outside of a build script.
+    pub fn from_env() -> Option<Self> {
+        return Self::from_vars(|key| std::env::var(key).ok());
+    }
+
+    pub(crate) fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let target = var("TARGET")?;
+        let names = |name: &str| env_names(name, &target);
+        let (cc_launcher, cc, cc_args) = match names("CC")
+            .iter()
+            .find_map(|name| var(name))
+            .as_deref()
+            .and_then(split_cc)
+        {
+            Some((launcher, cc, args)) => (launcher, Some(cc), args),
+            None => (None, None, Vec::new()),
+        };
+        let ar = names("AR")
+            .iter()
+            .find_map(|name| var(name))
+            .map(|ar| ar.trim().to_string())
+            .filter(|ar| !ar.is_empty());
+        // Every `CFLAGS` variant applies, most specific last.
+        let cflags = names("CFLAGS")
+            .iter()
+            .rev()
+            .filter_map(|name| var(name))
+            .flat_map(|flags| {
+                flags
+                    .split_whitespace()
+                    .map(|flag| flag.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        return Some(Self {
+            out_dir: var("OUT_DIR")?.into(),
+            manifest_dir: var("CARGO_MANIFEST_DIR")
+                .unwrap_or_else(|| ".".into())
+                .into(),
+            target,
+            profile: var("PROFILE").unwrap_or_else(|| "debug".into()),
+            opt_level: var("OPT_LEVEL"),
+            debug: var("DEBUG"),
+            cc,
+            cc_launcher,
+            cc_args,
+            cflags,
+            ar,
+        });
+    }
+
+    pub fn out_dir(&self) -> &PathBuf {
+        &self.out_dir
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn is_msvc(&self) -> bool {
+        return self.target.ends_with("-msvc");
+    }
+
+    fn compiler(&self) -> Compiler {
+        if self.is_msvc() {
+            return Compiler::Msvc;
+        }
+        if self.target.contains("-apple-") {
+            return Compiler::Clang;
+        }
+        return Compiler::Gcc;
+    }
+
+    // `CC` as probed, or as the family `TARGET` implies when it can't be run.
+    fn toolchain_info(&self, cc: &str) -> ToolchainInfo {
+        ToolchainInfo::probe(cc).unwrap_or_else(|| {
+            let family = match self.compiler() {
+                Compiler::Msvc => Family::Msvc,
+                Compiler::Clang => Family::Clang,
+                _ => Family::Gcc,
+            };
+            ToolchainInfo::new(cc, family, Version::default(), &self.target)
+        })
+    }
+
+    // `OPT_LEVEL` wins; `PROFILE` is the fallback for older Cargo versions.
+    fn opt_level(&self) -> OptimizationLevel {
+        let level = match &self.opt_level {
+            Some(level) => level.as_str(),
+            None if self.profile == "release" => "3",
+            None => "0",
+        };
+        match level {
+            "1" => return OptimizationLevel::O1,
+            "2" => return OptimizationLevel::O2,
+            "3" => return OptimizationLevel::O3,
+            "s" | "z" => return OptimizationLevel::Small,
+            _ => return OptimizationLevel::O0,
+        }
+    }
+
+    fn debug(&self) -> Option<DebugInfo> {
+        match self.debug.as_deref()? {
+            "false" | "0" | "none" => return None,
+            "1" | "limited" | "line-tables-only" | "line-directives-only" => {
+                return Some(DebugInfo::Level(1))
+            }
+            _ => return Some(DebugInfo::Default),
+        }
+    }
+
+    // Points `builder` at `OUT_DIR` and matches the compiler and flags to the Cargo profile.
+    // `CC` overrides the compiler `TARGET` implies, along with its launcher and arguments;
+    // those arguments and then `CFLAGS` go after every derived flag.
+    pub fn configure(&self, builder: CompileCommandBuilder) -> CompileCommandBuilder {
+        let builder = builder
+            .working_dir(&self.manifest_dir)
+            .out_dir(&self.out_dir)
+            .compiler(self.compiler());
+        let builder = match &self.cc {
+            Some(cc) => builder.toolchain_info(self.toolchain_info(cc)),
+            None => builder,
+        };
+        let builder = match &self.cc_launcher {
+            Some(launcher) => builder.launcher(launcher.clone()),
+            None => builder,
+        };
+        let builder = builder
+            .opt_level(self.opt_level())
+            .pic(!self.is_msvc())
+            .flags(&self.cc_args)
+            .flags(&self.cflags);
+        match self.debug() {
+            Some(debug) => return builder.debug_info(debug),
+            None => return builder,
+        }
+    }
+
+    fn lib_name(&self, name: &str) -> String {
+        if self.is_msvc() {
+            return name.to_string();
+        }
+        return format!("lib{}", name);
+    }
+
+    // Compiles `command`, archives it into a static library called `name` in
+    // `OUT_DIR` and tells Cargo to link it.
+    pub fn compile(&self, name: &str, command: &mut CompileCommand) -> Status {
+        let status = command.run();
+        // Rerun triggers are useful even when the build failed.
+        for directive in Self::rerun_directives(command)
+            .into_iter()
+            .chain(self.env_directives())
+        {
+            println!("{}", directive);
+        }
+        if let Status::Aborted = status {
+            return Status::Aborted;
+        }
+
+        let linker = match self.is_msvc() {
+            true => Linker::Link,
+            false => Linker::Gcc,
+        };
+        let mut lib = LinkerCommand::builder(command)
+            .linker(linker)
+            .bin_type(BinType::StaticLib)
+            .name(self.lib_name(name));
+        if let Some(ar) = &self.ar {
+            lib = lib.archiver(ar);
+        }
+        let mut lib = lib.build();
+        if let Status::Aborted = lib.run() {
+            event!(Level::ERROR, "failed to archive `{}`", name);
+            return Status::Aborted;
+        }
+        for directive in self.link_directives(name) {
+            println!("{}", directive);
+        }
+        return Status::Success;
+    }
+
+    pub fn link_directives(&self, name: &str) -> Vec<String> {
+        return Vec::from([
+            format!("cargo:rustc-link-search=native={}", self.out_dir.display()),
+            format!("cargo:rustc-link-lib=static={}", name),
+        ]);
+    }
+
+    // Every variable `configure` and `compile` read, so changing one rebuilds.
+    pub fn env_directives(&self) -> Vec<String> {
+        return ["CC", "CFLAGS", "AR"]
+            .iter()
+            .flat_map(|name| env_names(name, &self.target))
+            .map(|name| format!("cargo:rerun-if-env-changed={}", name))
+            .collect();
+    }
+
+    // Every source, resource, step input, the precompiled header and each header the
+    // compiler reported reading. Generated files are left out; their inputs cover them.
+    pub fn rerun_directives(command: &CompileCommand) -> Vec<String> {
+        let sources = command
+            .files()
+            .iter()
+            .map(|file| file.src().to_path_buf())
+            .chain(
+                command
+                    .resources()
+                    .iter()
+                    .map(|res| res.path().to_path_buf()),
+            );
+        let step_inputs = command
+            .steps()
+            .iter()
+            .flat_map(|step| step.inputs().iter().cloned());
+        let pch = command.pch_header().map(|header| header.to_path_buf());
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for path in sources
+            .chain(step_inputs)
+            .chain(pch)
+            .chain(command.headers())
+            .filter(|path| !command.is_generated(path))
+        {
+            let path = command.working_dir().join(path);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        return paths
+            .iter()
+            .map(|path| format!("cargo:rerun-if-changed={}", path.display()))
+            .collect();
+    }
+}
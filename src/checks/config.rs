@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+// A configuration header, either rendered from a CMake-style template
+// (`@VAR@`, `#cmakedefine`, `#cmakedefine01`) or emitted from the values alone.
+#[derive(Debug, Clone)]
+pub struct ConfigHeader {
+    name: PathBuf,
+    template: Option<PathBuf>,
+    values: Vec<(String, Option<String>)>,
+}
+
+// CMake's notion of a false constant.
+fn is_truthy(value: Option<&str>) -> bool {
+    let Some(value) = value else {
+        return false;
+    };
+    let upper = value.to_ascii_uppercase();
+    return !(["", "0", "OFF", "NO", "FALSE", "N", "IGNORE", "NOTFOUND"].contains(&upper.as_str())
+        || upper.ends_with("-NOTFOUND"));
+}
+
+impl ConfigHeader {
+    // `name` is relative to `out_dir/include`, e.g. `config.h` or `mylib/config.h`.
+    pub fn new(name: impl Into<PathBuf>) -> Self {
+        return Self {
+            name: name.into(),
+            template: None,
+            values: Vec::new(),
+        };
+    }
+
+    // Template file, relative to the command's working directory.
+    pub fn template(mut self, template: impl Into<PathBuf>) -> Self {
+        self.template = Some(template.into());
+        return self;
+    }
+
+    fn set(mut self, name: &str, value: Option<String>) -> Self {
+        match self.values.iter_mut().find(|(key, _)| key == name) {
+            Some((_, old)) => *old = value,
+            None => self.values.push((name.to_string(), value)),
+        }
+        return self;
+    }
+
+    pub fn define(self, name: &str, value: impl ToString) -> Self {
+        return self.set(name, Some(value.to_string()));
+    }
+
+    // Defined to `1` when set, left undefined otherwise, like autoconf's `HAVE_*` macros.
+    pub fn flag(self, name: &str, set: bool) -> Self {
+        return self.set(name, set.then(|| "1".to_string()));
+    }
+
+    pub fn undef(self, name: &str) -> Self {
+        return self.set(name, None);
+    }
+
+    pub fn name(&self) -> &Path {
+        &self.name
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        return self
+            .values
+            .iter()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| value.as_deref());
+    }
+
+    pub(crate) fn render(&self, working_dir: &Path) -> std::io::Result<String> {
+        match &self.template {
+            Some(template) => {
+                let text = std::fs::read_to_string(working_dir.join(template))?;
+                return Ok(self.render_template(&text));
+            }
+            None => return Ok(self.render_values()),
+        }
+    }
+
+    // Replaces `@VAR@` references; undefined variables expand to nothing.
+    fn substitute(&self, line: &str) -> String {
+        let mut out = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find('@') {
+            let Some(len) = rest[start + 1..].find('@') else {
+                break;
+            };
+            let name = &rest[start + 1..start + 1 + len];
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                out.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+                continue;
+            }
+            out.push_str(&rest[..start]);
+            out.push_str(self.get(name).unwrap_or_default());
+            rest = &rest[start + len + 2..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    pub fn render_template(&self, template: &str) -> String {
+        let mut out = String::new();
+        for line in template.lines() {
+            let directive = line
+                .trim_start()
+                .strip_prefix('#')
+                .map(|rest| rest.trim_start());
+            let rendered = match directive {
+                Some(rest) if rest.starts_with("cmakedefine01 ") => {
+                    let name = rest["cmakedefine01 ".len()..].trim();
+                    let value = is_truthy(self.get(name)) as u8;
+                    format!("#define {} {}", name, value)
+                }
+                Some(rest) if rest.starts_with("cmakedefine ") => {
+                    let rest = rest["cmakedefine ".len()..].trim_start();
+                    let (name, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    match is_truthy(self.get(name)) {
+                        true if tail.trim().is_empty() => format!("#define {}", name),
+                        true => format!("#define {} {}", name, self.substitute(tail.trim())),
+                        false => format!("/* #undef {} */", name),
+                    }
+                }
+                _ => self.substitute(line),
+            };
+            out.push_str(&rendered);
+            out.push('\n');
+        }
+        out
+    }
+
+    fn render_values(&self) -> String {
+        let guard = self
+            .name
+            .display()
+            .to_string()
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect::<String>();
+        let mut out = String::from("/* Generated by cbuild; do not edit. */\n");
+        out.push_str(&format!("#ifndef {}\n#define {}\n\n", guard, guard));
+        for (name, value) in &self.values {
+            match value {
+                Some(value) => out.push_str(&format!("#define {} {}\n", name, value)),
+                None => out.push_str(&format!("/* #undef {} */\n", name)),
+            }
+        }
+        out.push_str(&format!("\n#endif /* {} */\n", guard));
+        out
+    }
+}
@@ -0,0 +1,279 @@
+use crate::compiler::CompileCommand;
+use crate::hash::Hasher;
+use crate::invocation::Invocation;
+use crate::linker::BinType;
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+mod config;
+
+pub use config::ConfigHeader;
+
+// Keeps probes working when a C++ compiler is configured: C linkage for the
+// functions we declare ourselves.
+const EXTERN_C: &str = "#ifdef __cplusplus\nextern \"C\"\n#endif\n";
+
+// `sizeof` gives up past this many bytes instead of probing forever.
+const MAX_SIZEOF: usize = 1 << 16;
+
+// Configure-time probes in the style of autoconf: tiny programs compiled (and
+// linked) with the command's toolchain and flags in `out_dir/checks`. Results are
+// cached per toolchain and flag set, so repeated builds don't pay for them again.
+pub struct Checks<'a> {
+    command: &'a CompileCommand,
+    libs: Vec<PathBuf>,
+    scratch: PathBuf,
+    fingerprint: String,
+    results: Vec<(String, String)>,
+}
+
+impl<'a> Checks<'a> {
+    pub fn new(command: &'a CompileCommand) -> Self {
+        let mut checks = Self {
+            command,
+            libs: Vec::new(),
+            scratch: command.out_dir().join("checks"),
+            fingerprint: command.toolchain_fingerprint(),
+            results: Vec::new(),
+        };
+        checks.load();
+        return checks;
+    }
+
+    // Libraries linked into every link test, e.g. `m` for math functions.
+    pub fn lib(mut self, lib: impl Into<PathBuf>) -> Self {
+        self.libs.push(lib.into());
+        self.load();
+        return self;
+    }
+
+    fn cache_file(&self) -> PathBuf {
+        let mut hasher = Hasher::new();
+        hasher.field(self.fingerprint.as_bytes());
+        for flag in self.command.flags() {
+            hasher.field(flag.as_bytes());
+        }
+        for lib in &self.libs {
+            hasher.field(lib.display().to_string().as_bytes());
+        }
+        return self.command.resolve(
+            &self
+                .scratch
+                .join(format!("checks-{}.cache", hasher.finish())),
+        );
+    }
+
+    fn load(&mut self) {
+        self.results = std::fs::read_to_string(self.cache_file())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.rsplit_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+    }
+
+    fn save(&self) {
+        let mut content = String::new();
+        for (key, value) in &self.results {
+            content.push_str(&format!("{}={}\n", key, value));
+        }
+        if let Err(e) = std::fs::write(self.cache_file(), content) {
+            event!(Level::WARN, "failed to write checks cache: `{}`", e);
+        }
+    }
+
+    // Looks `key` up in the cache, running `check` and recording its result on a miss.
+    fn cached(&mut self, key: String, check: impl FnOnce(&Self) -> String) -> String {
+        if let Some((_, value)) = self.results.iter().find(|(k, _)| *k == key) {
+            return value.clone();
+        }
+        let value = check(self);
+        println!(
+            "[Check]: {} ... {}",
+            key,
+            if value.is_empty() { "no" } else { &value }
+        );
+        self.results.push((key, value.clone()));
+        self.save();
+        return value;
+    }
+
+    fn run(&self, invocation: Invocation) -> bool {
+        event!(Level::DEBUG, "executing: {}", invocation);
+        match invocation.to_command().output() {
+            Ok(out) => {
+                if !out.status.success() {
+                    event!(
+                        Level::DEBUG,
+                        "check failed: {}",
+                        String::from_utf8_lossy(&out.stderr)
+                    );
+                }
+                return out.status.success();
+            }
+            Err(e) => {
+                event!(Level::WARN, "error occured: `{}`", e);
+                return false;
+            }
+        }
+    }
+
+    // Writes `source` to the scratch directory under a name derived from its content.
+    fn write_source(&self, source: &str) -> Option<PathBuf> {
+        let mut hasher = Hasher::new();
+        hasher.field(source.as_bytes());
+        let src = self.scratch.join(format!("check_{}.c", hasher.finish()));
+        let resolved = self.command.resolve(&src);
+        if let Err(e) = std::fs::create_dir_all(self.command.resolve(&self.scratch))
+            .and_then(|_| std::fs::write(&resolved, source))
+        {
+            event!(Level::WARN, "failed to write check source: `{}`", e);
+            return None;
+        }
+        return Some(src);
+    }
+
+    fn compile_invocation(&self, src: &Path, extra: &[String]) -> Invocation {
+        let toolchain = self.command.toolchain();
+        let obj = src.with_extension(toolchain.object_ext());
+        return Invocation::new(self.command.compiler())
+            .cwd(self.command.working_dir())
+            .args(toolchain.compile_preamble())
+            .args(toolchain.src_file(src, &obj))
+            .args(self.command.flags())
+            .args(extra);
+    }
+
+    fn try_compile(&self, source: &str, extra: &[String]) -> bool {
+        let Some(src) = self.write_source(source) else {
+            return false;
+        };
+        return self.run(self.compile_invocation(&src, extra));
+    }
+
+    fn try_link(&self, source: &str) -> bool {
+        let Some(src) = self.write_source(source) else {
+            return false;
+        };
+        if !self.run(self.compile_invocation(&src, &[])) {
+            return false;
+        }
+        let toolchain = self.command.toolchain();
+        let obj = src.with_extension(toolchain.object_ext());
+        let mut cmd = Invocation::new(self.command.link_driver(&BinType::Binary))
+            .cwd(self.command.working_dir())
+            .args(toolchain.link_preamble(&BinType::Binary))
+            .args(toolchain.link_out(&src.with_extension("exe")))
+            .arg(obj.display());
+        for lib in &self.libs {
+            cmd = cmd.args(toolchain.link_lib(lib));
+        }
+        return self.run(cmd.args(self.command.link_flags(toolchain)));
+    }
+
+    fn yes_no(value: bool) -> String {
+        match value {
+            true => return "yes".into(),
+            false => return String::new(),
+        }
+    }
+
+    // True if the source compiles with the command's flags.
+    pub fn compiles(&mut self, source: &str) -> bool {
+        let mut hasher = Hasher::new();
+        hasher.field(source.as_bytes());
+        let key = format!("compiles {}", hasher.finish());
+        return !self
+            .cached(key, |checks| Self::yes_no(checks.try_compile(source, &[])))
+            .is_empty();
+    }
+
+    // True if the source compiles and links into an executable.
+    pub fn links(&mut self, source: &str) -> bool {
+        let mut hasher = Hasher::new();
+        hasher.field(source.as_bytes());
+        let key = format!("links {}", hasher.finish());
+        return !self
+            .cached(key, |checks| Self::yes_no(checks.try_link(source)))
+            .is_empty();
+    }
+
+    pub fn has_header(&mut self, header: &str) -> bool {
+        let source = format!("#include <{}>\nint main(void) {{ return 0; }}\n", header);
+        return !self
+            .cached(format!("header {}", header), |checks| {
+                Self::yes_no(checks.try_compile(&source, &[]))
+            })
+            .is_empty();
+    }
+
+    // Declares `name` ourselves so no header is needed; only whether it links matters.
+    pub fn has_function(&mut self, name: &str) -> bool {
+        let source = format!(
+            "{}char {}(void);\nint main(void) {{ return (int){}(); }}\n",
+            EXTERN_C, name, name
+        );
+        return !self
+            .cached(format!("function {}", name), |checks| {
+                Self::yes_no(checks.try_link(&source))
+            })
+            .is_empty();
+    }
+
+    // Warnings are errors here, since compilers tend to only warn about flags they ignore.
+    pub fn accepts_flag(&mut self, flag: &str) -> bool {
+        let extra = [
+            flag.to_string(),
+            self.command.toolchain().warnings_as_errors(),
+        ];
+        return !self
+            .cached(format!("flag {}", flag), |checks| {
+                Self::yes_no(checks.try_compile("int main(void) { return 0; }\n", &extra))
+            })
+            .is_empty();
+    }
+
+    // Found without running anything, so it also works when cross-compiling: a
+    // negative array size fails to compile, which turns `sizeof(ty) <= n` into a
+    // compile test to binary search over.
+    pub fn sizeof(&mut self, ty: &str) -> Option<usize> {
+        let value = self.cached(format!("sizeof {}", ty), |checks| {
+            let fits = |n: usize| {
+                let source = format!(
+                    "#include <stddef.h>\n#include <stdint.h>\n\
+                     static char probe[(sizeof({}) <= {}) ? 1 : -1];\n\
+                     int main(void) {{ return probe[0]; }}\n",
+                    ty, n
+                );
+                checks.try_compile(&source, &[])
+            };
+            let exists = format!(
+                "#include <stddef.h>\n#include <stdint.h>\n\
+                 static char probe[sizeof({})];\n\
+                 int main(void) {{ return probe[0]; }}\n",
+                ty
+            );
+            if !checks.try_compile(&exists, &[]) {
+                return String::new();
+            }
+            let mut high = 1;
+            while !fits(high) {
+                high *= 2;
+                if high > MAX_SIZEOF {
+                    return String::new();
+                }
+            }
+            let mut low = high / 2;
+            while high - low > 1 {
+                let mid = (low + high) / 2;
+                if fits(mid) {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            high.to_string()
+        });
+        return value.parse().ok();
+    }
+}
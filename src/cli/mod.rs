@@ -0,0 +1,183 @@
+use crate::compiler::{CompilationFile, CompileCommand};
+use crate::import::CompilationDatabase;
+use crate::invocation::Invocation;
+use crate::linker::LinkerCommand;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: cbuild run [options] <file>... [-- <args>...]
+
+Builds an executable from the given sources, or from a compilation database,
+and runs it with <args>. Exits with the program's exit code.
+
+options:
+    -C <dir>                     run as if started in <dir>
+    -o, --name <name>            executable name (default: app)
+    --out-dir <dir>              build directory (default: out)
+    -I <dir>                     add an include directory
+    -D <name[=value]>            add a definition
+    --compile-commands <file>    take sources and flags from compile_commands.json
+    --env <key=value>            set a variable in the program's environment
+    --wrapper <command>          run the program through a wrapper, e.g. \"valgrind -q\"
+";
+
+// A parsed `cbuild run` command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    files: Vec<PathBuf>,
+    includes: Vec<PathBuf>,
+    definitions: Vec<String>,
+    database: Option<PathBuf>,
+    dir: Option<PathBuf>,
+    name: String,
+    out_dir: PathBuf,
+    env: Vec<(String, String)>,
+    wrapper: Vec<String>,
+    args: Vec<String>,
+}
+
+impl Default for Run {
+    fn default() -> Self {
+        return Self {
+            files: Vec::new(),
+            includes: Vec::new(),
+            definitions: Vec::new(),
+            database: None,
+            dir: None,
+            name: "app".into(),
+            out_dir: "out".into(),
+            env: Vec::new(),
+            wrapper: Vec::new(),
+            args: Vec::new(),
+        };
+    }
+}
+
+// Takes an option's value either glued to it (`-Iinclude`) or as the next argument.
+fn value(arg: &str, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<String, String> {
+    if arg.len() > flag.len() && !flag.starts_with("--") {
+        return Ok(arg[flag.len()..].to_string());
+    }
+    return args.next().ok_or(format!("`{}` expects a value", flag));
+}
+
+impl Run {
+    // Parses the arguments after `run`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut run = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => {
+                    run.args.extend(args.by_ref());
+                }
+                "-C" => run.dir = Some(value(&arg, &arg, &mut args)?.into()),
+                "-o" | "--name" => run.name = value(&arg, &arg, &mut args)?,
+                "--out-dir" => run.out_dir = value(&arg, &arg, &mut args)?.into(),
+                "--compile-commands" => {
+                    run.database = Some(value(&arg, &arg, &mut args)?.into());
+                }
+                "--env" => {
+                    let pair = value(&arg, &arg, &mut args)?;
+                    let (key, val) = pair
+                        .split_once('=')
+                        .ok_or(format!("`--env {}` is not `key=value`", pair))?;
+                    run.env.push((key.into(), val.into()));
+                }
+                "--wrapper" => {
+                    let wrapper = value(&arg, &arg, &mut args)?;
+                    run.wrapper = wrapper.split_whitespace().map(String::from).collect();
+                }
+                _ if arg.starts_with("-I") => {
+                    run.includes.push(value(&arg, "-I", &mut args)?.into())
+                }
+                _ if arg.starts_with("-D") => run.definitions.push(value(&arg, "-D", &mut args)?),
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ => run.files.push(arg.into()),
+            }
+        }
+        if run.files.is_empty() && run.database.is_none() {
+            return Err("no source files given".into());
+        }
+        return Ok(run);
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    fn compile_command(&self) -> Option<CompileCommand> {
+        let mut builder = match &self.database {
+            Some(path) => {
+                let path = match &self.dir {
+                    Some(dir) => dir.join(path),
+                    None => path.clone(),
+                };
+                // Sources and flags are relative to the database's first entry.
+                CompilationDatabase::load(path)?.import().builder()
+            }
+            None => match &self.dir {
+                Some(dir) => CompileCommand::builder().working_dir(dir),
+                None => CompileCommand::builder(),
+            }
+            .detect_toolchain(),
+        };
+        for file in &self.files {
+            builder = builder.file(CompilationFile::new(file));
+        }
+        builder = builder
+            .includes(self.includes.clone())
+            .definitions(self.definitions.clone());
+        return Some(builder.out_dir(&self.out_dir).build());
+    }
+
+    fn configure(&self, mut run: Invocation) -> Invocation {
+        run = run.args(&self.args);
+        for (key, value) in &self.env {
+            run = run.env(key, value);
+        }
+        if let Some((program, args)) = self.wrapper.split_first() {
+            run = run.wrap(program, args);
+        }
+        return run;
+    }
+
+    // Builds what's out of date, then runs the executable. `None` if the build failed
+    // or the program couldn't be started.
+    pub fn execute(&self) -> Option<i32> {
+        let mut command = self.compile_command()?;
+        let mut link = LinkerCommand::builder(&mut command)
+            .name(&self.name)
+            .build();
+        return link.execute(|run| self.configure(run));
+    }
+}
+
+// Entry point of the `cbuild` binary; `args` excludes the program name. Returns the
+// process exit code.
+pub fn main(args: impl IntoIterator<Item = String>) -> i32 {
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        Some("run") => {}
+        Some("-h" | "--help") => {
+            print!("{}", USAGE);
+            return 0;
+        }
+        Some(command) => {
+            println!("[ERROR]: unknown command `{}`\n\n{}", command, USAGE);
+            return 2;
+        }
+        None => {
+            print!("{}", USAGE);
+            return 2;
+        }
+    }
+    let run = match Run::parse(args) {
+        Ok(run) => run,
+        Err(e) => {
+            println!("[ERROR]: {}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    return run.execute().unwrap_or(1);
+}
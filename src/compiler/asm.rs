@@ -0,0 +1,123 @@
+use std::path::Path;
+
+// What a source file is, going by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    // C or C++, depending on the command's `Lang`.
+    C,
+    // `.s`: GAS syntax, assembled by the compiler driver without preprocessing.
+    Assembly,
+    // `.S`/`.sx`: GAS syntax run through the C preprocessor first.
+    PreprocessedAssembly,
+    // `.asm`/`.nasm`: Intel syntax for NASM or YASM.
+    Nasm,
+}
+
+impl SourceKind {
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("s") => return Self::Assembly,
+            Some("S") | Some("sx") => return Self::PreprocessedAssembly,
+            Some("asm") | Some("nasm") => return Self::Nasm,
+            _ => return Self::C,
+        }
+    }
+
+    // Only C translation units can share a unity batch or use a precompiled header.
+    pub fn is_c(&self) -> bool {
+        return *self == Self::C;
+    }
+}
+
+// Assembler for `.asm` sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Assembler {
+    #[default]
+    Nasm,
+    Yasm,
+}
+
+impl Assembler {
+    pub fn program(&self) -> &'static str {
+        match self {
+            Assembler::Nasm => return "nasm",
+            Assembler::Yasm => return "yasm",
+        }
+    }
+
+    // Object format for `target`, a triple such as `x86_64-pc-windows-msvc`; the host's
+    // when the target is unknown (empty).
+    pub fn format(&self, target: &str) -> &'static str {
+        let (windows, apple, x86) = match target {
+            "" => (
+                std::env::consts::OS == "windows",
+                matches!(std::env::consts::OS, "macos" | "ios"),
+                std::env::consts::ARCH == "x86",
+            ),
+            target => (
+                ["windows", "mingw", "cygwin"]
+                    .iter()
+                    .any(|os| target.contains(os)),
+                target.contains("apple") || target.contains("darwin"),
+                ["i386-", "i486-", "i586-", "i686-"]
+                    .iter()
+                    .any(|arch| target.starts_with(arch)),
+            ),
+        };
+        match (windows, apple, x86) {
+            (true, _, true) => "win32",
+            (true, _, false) => "win64",
+            (_, true, true) => "macho32",
+            (_, true, false) => "macho64",
+            (_, _, true) => "elf32",
+            (_, _, false) => "elf64",
+        }
+    }
+
+    pub fn src_file(&self, src: &Path, out: &Path, target: &str) -> Vec<String> {
+        return Vec::from([
+            "-f".into(),
+            self.format(target).into(),
+            "-o".into(),
+            out.display().to_string(),
+            src.display().to_string(),
+        ]);
+    }
+
+    // Older NASM releases paste the file name straight onto the include path.
+    pub fn include(&self, dir: &Path) -> String {
+        let dir = dir.display().to_string();
+        if dir.ends_with('/') || dir.ends_with('\\') {
+            return format!("-I{}", dir);
+        }
+        return format!("-I{}/", dir);
+    }
+
+    pub fn definition(&self, def: &str) -> String {
+        return format!("-D{}", def);
+    }
+
+    pub fn debug(&self, target: &str) -> Vec<String> {
+        match (self, self.format(target)) {
+            (Assembler::Nasm, _) => return Vec::from(["-g".into()]),
+            (Assembler::Yasm, "elf32" | "elf64") => {
+                return Vec::from(["-g".into(), "dwarf2".into()])
+            }
+            (Assembler::Yasm, "win32" | "win64") => return Vec::from(["-g".into(), "cv8".into()]),
+            (Assembler::Yasm, _) => return Vec::new(),
+        }
+    }
+
+    // YASM can't write depfiles.
+    pub fn depfile(&self, dep: &Path) -> Option<Vec<String>> {
+        match self {
+            Assembler::Nasm => return Some(Vec::from(["-MD".into(), dep.display().to_string()])),
+            Assembler::Yasm => return None,
+        }
+    }
+
+    // Preprocesses `src` to stdout.
+    pub fn preprocess(&self, src: &Path) -> Vec<String> {
+        return Vec::from(["-E".into(), src.display().to_string()]);
+    }
+}
@@ -1,10 +1,26 @@
-use std::fs::FileType;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 //#[cfg(feature = "tracing")]
+use crate::cache::{ObjectCache, RemoteCache};
+use crate::checks::ConfigHeader;
+use crate::coverage::CoverageFormat;
+use crate::embed::{self, EmbedMode, Resource};
+use crate::hash::Hasher;
+use crate::invocation::Invocation;
+use crate::launcher::Launcher;
+use crate::linker::BinType;
 use crate::path::Normalize;
+use crate::pgo::PgoPhase;
+use crate::step::{self, CustomStep};
+use crate::toolchain::{self, find_in_path, Builtins, Probe, Toolchain, ToolchainInfo};
+use std::sync::Arc;
 use tracing::{event, span, Level, Span};
 
+mod asm;
+mod sanitize;
+
+pub use asm::{Assembler, SourceKind};
+pub use sanitize::Sanitizer;
+
 /*
  * TODO(S): remodel this
 #[derive(Debug, Clone)]
@@ -74,6 +90,29 @@ pub enum OptimizationLevel {
     Fast,
 }
 
+// Debug info as each toolchain spells it: `-g`/`-g<n>` for GCC-style drivers, `/Z7`
+// for MSVC, which has no levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugInfo {
+    Default,
+    Level(u8),
+}
+
+// A verbatim flag from `debug`, or debug info the toolchain translates.
+#[derive(Clone)]
+enum Debug {
+    Flag(String),
+    Info(DebugInfo),
+}
+
+// Link-time optimization. Thin LTO splits the link-time work into parallel jobs;
+// toolchains without it fall back to their regular LTO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lto {
+    Full,
+    Thin,
+}
+
 impl Compiler {
     const fn sys_default() -> Self {
         #[cfg(target_os = "windows")]
@@ -84,54 +123,140 @@ impl Compiler {
         return Self::Gcc;
     }
 
-    fn as_str(&self, lang: Lang) -> &'static str {
-        match (self, lang) {
-            (Self::Clang, Lang::C) => return "clang",
-            (Self::Clang, Lang::Cpp) => return "clang++",
-            (Self::Gcc, Lang::C) => return "gcc",
-            (Self::Gcc, Lang::Cpp) => return "g++",
-            (Self::Msvc, Lang::C | Lang::Cpp) => return "cl",
+    pub fn toolchain(&self) -> Arc<dyn Toolchain> {
+        match self {
+            Self::Clang => return Arc::new(toolchain::Clang),
+            Self::Gcc => return Arc::new(toolchain::Gcc),
+            Self::Msvc => return Arc::new(toolchain::Msvc),
         }
     }
 }
 
 #[derive(Clone)]
 pub struct CompileCommand {
-    compiler: Compiler,
+    toolchain: Arc<dyn Toolchain>,
+    toolchain_info: Option<ToolchainInfo>,
     lang: Lang,
     optimization_level: Option<OptimizationLevel>,
-    debug: Option<String>,
+    debug: Option<Debug>,
     files: Vec<CompilationFile>,
     includes: Vec<PathBuf>,
     definitions: Vec<String>,
+    // Passed through verbatim after every flag the command derives itself.
+    extra_flags: Vec<String>,
     std: Option<Std>,
     target: Option<Target>,
+    pic: bool,
+    sanitizers: Vec<Sanitizer>,
+    lto: Option<Lto>,
+    coverage: bool,
+    pgo: Option<PgoPhase>,
     out_dir: PathBuf,
     //#[cfg(feature = "tracing")]
     tracing: Span,
     dirs: Vec<PathBuf>,
     working_directory: PathBuf,
+    pch: Option<PrecompiledHeader>,
+    unity: Option<Unity>,
+    // Outside `unity` so exclusions hold whether they come before or after `.unity()`.
+    unity_exclude: Vec<PathBuf>,
+    launcher: Option<Launcher>,
+    cache: Option<ObjectCache>,
+    remote_cache: Option<RemoteCache>,
+    resources: Vec<Resource>,
+    embed_mode: EmbedMode,
+    steps: Vec<CustomStep>,
+    assembler: Assembler,
 }
 
+#[derive(Clone)]
 pub struct CompileCommandBuilder {
     inner: CompileCommand,
+    detect: bool,
+}
+
+#[derive(Clone)]
+struct Unity {
+    batch_size: usize,
+    units: Vec<CompilationFile>,
+}
+
+#[derive(Clone)]
+struct PrecompiledHeader {
+    header: PathBuf,
+    out: PathBuf,
+    object: Option<PathBuf>,
+}
+
+fn write_if_changed(path: &Path, content: &str) -> std::io::Result<()> {
+    if std::fs::read_to_string(path).ok().as_deref() == Some(content) {
+        return Ok(());
+    }
+    return std::fs::write(path, content);
+}
+
+fn modified_time(path: &Path) -> Option<std::time::SystemTime> {
+    return path.metadata().ok()?.modified().ok();
+}
+
+// True if `out` is missing or older than any of `inputs`. Callers resolve both against
+// the working directory, which is where the compiler reads and writes them.
+pub(crate) fn is_outdated(out: &Path, inputs: impl IntoIterator<Item = impl AsRef<Path>>) -> bool {
+    let Some(out_time) = modified_time(out) else {
+        return true;
+    };
+    return inputs
+        .into_iter()
+        .any(|input| match modified_time(input.as_ref()) {
+            Some(time) => out_time < time,
+            None => true,
+        });
+}
+
+// Prerequisites of the first rule in a Makefile-style depfile, as written by `-MMD`.
+pub(crate) fn parse_depfile(content: &str) -> Vec<PathBuf> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let rule = content.lines().next().unwrap_or_default();
+    let Some((_, prerequisites)) = rule.split_once(": ") else {
+        return Vec::new();
+    };
+    let mut deps = Vec::new();
+    let mut current = String::new();
+    let mut chars = prerequisites.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => current.push(chars.next().unwrap_or(' ')),
+            '$' if chars.peek() == Some(&'$') => current.push(chars.next().unwrap_or('$')),
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    deps.push(PathBuf::from(std::mem::take(&mut current)));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        deps.push(PathBuf::from(current));
+    }
+    deps
 }
 
 #[derive(Clone)]
 pub struct CompilationFile {
     src: PathBuf,
     out: PathBuf,
+    deps: Vec<PathBuf>,
 }
 
-impl Into<CompilationFile> for String {
-    fn into(self) -> CompilationFile {
-        CompilationFile::new(self)
+impl From<String> for CompilationFile {
+    fn from(src: String) -> Self {
+        CompilationFile::new(src)
     }
 }
 
-impl Into<CompilationFile> for &str {
-    fn into(self) -> CompilationFile {
-        CompilationFile::new(self)
+impl From<&str> for CompilationFile {
+    fn from(src: &str) -> Self {
+        CompilationFile::new(src)
     }
 }
 
@@ -160,10 +285,22 @@ impl CompilationFile {
         let src = src.into().normalize();
         let mut out = src.clone();
         out.set_extension("o");
-        return Self { src, out };
+        return Self {
+            src,
+            out,
+            deps: Vec::new(),
+        };
+    }
+
+    pub(crate) fn from_parts(src: impl Into<PathBuf>, out: impl Into<PathBuf>) -> Self {
+        return Self {
+            src: src.into(),
+            out: out.into(),
+            deps: Vec::new(),
+        };
     }
 
-    fn prepend_outdir(&mut self, out: &PathBuf) {
+    fn prepend_outdir(&mut self, out: &Path) {
         //if let Some(name) = self.out.file_stem() {
         //self.out = out.join(name);
         self.out = out.join(&self.out);
@@ -178,45 +315,146 @@ impl CompilationFile {
     pub fn out(&self) -> &Path {
         &self.out
     }
+
+    // Extra inputs besides `src` that make the object stale when they change.
+    pub fn deps(&self) -> &[PathBuf] {
+        &self.deps
+    }
 }
 
 impl CompileCommandBuilder {
     fn new() -> Self {
         return Self {
             inner: CompileCommand {
-                compiler: Compiler::sys_default(),
+                toolchain: Compiler::sys_default().toolchain(),
+                toolchain_info: None,
                 lang: Lang::C,
                 optimization_level: None,
                 debug: None,
                 files: Vec::new(),
                 includes: Vec::new(),
                 definitions: Vec::new(),
+                extra_flags: Vec::new(),
                 std: None,
                 target: None,
+                pic: false,
+                sanitizers: Vec::new(),
+                lto: None,
+                coverage: false,
+                pgo: None,
                 out_dir: "./".into(),
                 //#[cfg(feature = "tracing")]
                 tracing: span!(Level::INFO, "compile-command"),
                 dirs: Vec::new(),
                 working_directory: PathBuf::from("."),
+                pch: None,
+                unity: None,
+                unity_exclude: Vec::new(),
+                launcher: None,
+                cache: None,
+                remote_cache: None,
+                resources: Vec::new(),
+                embed_mode: EmbedMode::default(),
+                steps: Vec::new(),
+                assembler: Assembler::default(),
             },
+            detect: false,
         };
     }
 
     fn normalize_path(&mut self) {
         let out_dir = self.inner.out_dir.join("obj");
+        let object_ext = self.inner.toolchain.object_ext();
         self.inner.files.iter_mut().for_each(|file| {
             file.prepend_outdir(&out_dir);
-            if self.inner.compiler == Compiler::Msvc {
-                file.out.set_extension("obj");
-            }
+            file.out.set_extension(object_ext);
         });
         self.inner
             .includes
             .iter_mut()
             .for_each(|file| *file = file.normalize());
+        if let Some(pch) = &mut self.inner.pch {
+            let mut name = pch.header.file_name().unwrap_or_default().to_os_string();
+            name.push(".");
+            name.push(self.inner.toolchain.pch_ext());
+            pch.out = self.inner.out_dir.join("pch").join(name);
+            pch.object = self.inner.toolchain.pch_object(&pch.out);
+        }
+        self.add_resource_units();
+        self.build_unity_units();
+    }
+
+    // Each resource becomes a generated source in `out_dir/embed` that is rebuilt
+    // whenever the resource itself changes.
+    fn add_resource_units(&mut self) {
+        if self.inner.resources.is_empty() {
+            return;
+        }
+        let embed_dir = self.inner.out_dir.join("embed").normalize();
+        for resource in &self.inner.resources {
+            let src = embed_dir.join(format!("{}.c", resource.symbol()));
+            let mut out = self
+                .inner
+                .out_dir
+                .join("obj")
+                .join("embed")
+                .join(resource.symbol())
+                .normalize();
+            out.set_extension(self.inner.toolchain.object_ext());
+            // Kept out of unity batches, which only track their members' sources.
+            self.inner.unity_exclude.push(src.clone());
+            self.inner.files.push(CompilationFile {
+                src,
+                out,
+                deps: Vec::from([resource.path().to_path_buf()]),
+            });
+        }
+        if !self.inner.includes.contains(&embed_dir) {
+            self.inner.includes.push(embed_dir);
+        }
+    }
+
+    fn build_unity_units(&mut self) {
+        let Some(unity) = &mut self.inner.unity else {
+            return;
+        };
+        let ext = match self.inner.lang {
+            Lang::C => "c",
+            Lang::Cpp => "cpp",
+        };
+        let (excluded, batched): (Vec<_>, Vec<_>) = self.inner.files.iter().partition(|file| {
+            self.inner.unity_exclude.contains(&file.src) || !SourceKind::of(&file.src).is_c()
+        });
+        unity.units = batched
+            .chunks(unity.batch_size.max(1))
+            .enumerate()
+            .map(|(i, batch)| {
+                let src = self
+                    .inner
+                    .out_dir
+                    .join("unity")
+                    .join(format!("unity_{}.{}", i, ext));
+                let mut out = self
+                    .inner
+                    .out_dir
+                    .join("obj")
+                    .join("unity")
+                    .join(format!("unity_{}", i));
+                out.set_extension(self.inner.toolchain.object_ext());
+                CompilationFile {
+                    src,
+                    out,
+                    deps: batch.iter().map(|file| file.src.clone()).collect(),
+                }
+            })
+            .chain(excluded.into_iter().cloned())
+            .collect();
     }
 
     pub fn build(mut self) -> CompileCommand {
+        if self.detect {
+            self.detect();
+        }
         self.normalize_path();
         return self.inner;
     }
@@ -227,10 +465,47 @@ impl CompileCommandBuilder {
     }
 
     pub fn compiler(mut self, compiler: Compiler) -> Self {
-        self.inner.compiler = compiler;
+        self.inner.toolchain = compiler.toolchain();
+        self.inner.toolchain_info = None;
+        self.detect = false;
         return self;
     }
 
+    pub fn toolchain(mut self, toolchain: impl Toolchain + 'static) -> Self {
+        self.inner.toolchain = Arc::new(toolchain);
+        self.inner.toolchain_info = None;
+        self.detect = false;
+        return self;
+    }
+
+    pub fn toolchain_info(mut self, info: ToolchainInfo) -> Self {
+        self.inner.toolchain = info.toolchain();
+        self.inner.toolchain_info = Some(info);
+        self.detect = false;
+        return self;
+    }
+
+    // Probes `PATH` for a compiler when the command is built, caching the result in
+    // the final `out_dir`; keeps the current toolchain if nothing usable is found.
+    pub fn detect_toolchain(mut self) -> Self {
+        self.detect = true;
+        self
+    }
+
+    fn detect(&mut self) {
+        let probe = Probe::new(&self.inner.out_dir);
+        match probe.detect() {
+            Some(info) => {
+                self.inner.toolchain = info.toolchain();
+                self.inner.toolchain_info = Some(info);
+            }
+            None => {
+                let _guard = self.inner.tracing.enter();
+                event!(Level::WARN, "no compiler found in `PATH`");
+            }
+        }
+    }
+
     pub fn kind(mut self, lang: Lang) -> Self {
         self.inner.lang = lang;
         return self;
@@ -241,8 +516,15 @@ impl CompileCommandBuilder {
         return self;
     }
 
+    // Passed through verbatim as the debug flag, e.g. `"-g3"`. `debug_info` works
+    // with every toolchain.
     pub fn debug(mut self, debug_output: String) -> Self {
-        self.inner.debug = Some(debug_output);
+        self.inner.debug = Some(Debug::Flag(debug_output));
+        return self;
+    }
+
+    pub fn debug_info(mut self, info: DebugInfo) -> Self {
+        self.inner.debug = Some(Debug::Info(info));
         return self;
     }
 
@@ -251,7 +533,15 @@ impl CompileCommandBuilder {
         return self;
     }
 
-    pub fn file(mut self, mut file: CompilationFile) -> Self {
+    pub(crate) fn get_out_dir(&self) -> &Path {
+        &self.inner.out_dir
+    }
+
+    pub(crate) fn get_working_dir(&self) -> &Path {
+        &self.inner.working_directory
+    }
+
+    pub fn file(mut self, file: CompilationFile) -> Self {
         if let Some(dir) = file.src.parent() {
             self.inner.dirs.push(dir.to_path_buf());
         }
@@ -261,7 +551,7 @@ impl CompileCommandBuilder {
     }
 
     pub fn files(mut self, files: impl Into<Vec<CompilationFile>>) -> Self {
-        let mut files = files.into();
+        let files = files.into();
         files.iter().for_each(|file| {
             if let Some(dir) = file.src.parent() {
                 self.inner.dirs.push(dir.to_path_buf());
@@ -273,9 +563,8 @@ impl CompileCommandBuilder {
     }
 
     pub fn set_files(mut self, files: impl Into<Vec<CompilationFile>>) -> Self {
-        unimplemented!();
-        self.inner.files = files.into();
-        return self;
+        self.inner.files.clear();
+        return self.files(files);
     }
 
     pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
@@ -285,13 +574,11 @@ impl CompileCommandBuilder {
         }
         self.inner.dirs.push(dir.clone());
         if let Ok(dir) = dir.read_dir() {
-            for file in dir {
-                if let Ok(file) = file {
-                    if file.path().is_dir() {
-                        self = self.dir(file.path());
-                    } else {
-                        self.inner.files.push(CompilationFile::new(file.path()));
-                    }
+            for file in dir.flatten() {
+                if file.path().is_dir() {
+                    self = self.dir(file.path());
+                } else {
+                    self.inner.files.push(CompilationFile::new(file.path()));
                 }
             }
         }
@@ -328,6 +615,24 @@ impl CompileCommandBuilder {
         return self;
     }
 
+    pub fn flag(mut self, flag: impl ToString) -> Self {
+        self.inner.extra_flags.push(flag.to_string());
+        self
+    }
+
+    pub fn flags(mut self, flags: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.inner
+            .extra_flags
+            .extend(flags.into_iter().map(|flag| flag.to_string()));
+        self
+    }
+
+    // Used for `.asm` sources.
+    pub fn assembler(mut self, assembler: Assembler) -> Self {
+        self.inner.assembler = assembler;
+        return self;
+    }
+
     pub fn std(mut self, std: Std) -> Self {
         self.inner.std = Some(std);
         return self;
@@ -337,6 +642,116 @@ impl CompileCommandBuilder {
         self.inner.target = Some(target);
         return self;
     }
+
+    pub fn pic(mut self, pic: bool) -> Self {
+        self.inner.pic = pic;
+        return self;
+    }
+
+    // Honored by any `LinkerCommand` built on this command as well.
+    pub fn lto(mut self, lto: Lto) -> Self {
+        self.inner.lto = Some(lto);
+        return self;
+    }
+
+    // Instruments the objects for `coverage::CoverageReport`; linked in like sanitizers.
+    pub fn coverage(mut self, coverage: bool) -> Self {
+        self.inner.coverage = coverage;
+        return self;
+    }
+
+    // Usually set by `pgo::Pgo`, which runs both phases.
+    pub fn pgo(mut self, phase: PgoPhase) -> Self {
+        self.inner.pgo = Some(phase);
+        return self;
+    }
+
+    // Also linked in by any `LinkerCommand` built on this command.
+    pub fn sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        if !self.inner.sanitizers.contains(&sanitizer) {
+            self.inner.sanitizers.push(sanitizer);
+        }
+        return self;
+    }
+
+    // Compiles the files in generated batches of `batch_size` translation units.
+    pub fn unity(mut self, batch_size: usize) -> Self {
+        self.inner.unity = Some(Unity {
+            batch_size,
+            units: Vec::new(),
+        });
+        return self;
+    }
+
+    // Keeps `file` out of the unity batches; it is compiled on its own.
+    pub fn unity_exclude(mut self, file: impl Into<PathBuf>) -> Self {
+        self.inner.unity_exclude.push(file.into().normalize());
+        return self;
+    }
+
+    pub fn launcher(mut self, launcher: Launcher) -> Self {
+        self.inner.launcher = Some(launcher);
+        return self;
+    }
+
+    // Uses ccache or sccache if either is installed.
+    pub fn detect_launcher(mut self) -> Self {
+        self.inner.launcher = Launcher::detect();
+        return self;
+    }
+
+    // Restores objects from `cache` instead of compiling when the inputs match.
+    pub fn cache(mut self, cache: ObjectCache) -> Self {
+        self.inner.cache = Some(cache);
+        return self;
+    }
+
+    // Shares cached objects over HTTP; consulted after the local cache.
+    pub fn remote_cache(mut self, remote: RemoteCache) -> Self {
+        self.inner.remote_cache = Some(remote);
+        return self;
+    }
+
+    // Compiles `path` in as `const unsigned char <name>[]`, declared in `<name>.h`,
+    // with the name derived from the file name. Files that share a name in different
+    // directories need `embed_as`; `run` rejects the clash.
+    pub fn embed(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into().normalize();
+        let symbol = embed::symbol_for(&path);
+        return self.embed_as(path, symbol);
+    }
+
+    pub fn embed_as(mut self, path: impl Into<PathBuf>, symbol: impl ToString) -> Self {
+        self.inner
+            .resources
+            .push(Resource::new(path.into().normalize(), symbol));
+        return self;
+    }
+
+    pub fn embed_mode(mut self, mode: EmbedMode) -> Self {
+        self.inner.embed_mode = mode;
+        return self;
+    }
+
+    // Runs `step` before compiling; the sources it generates are compiled too.
+    pub fn step(mut self, step: CustomStep) -> Self {
+        let sources = step
+            .sources()
+            .map(|src| CompilationFile::new(src.clone()))
+            .collect::<Vec<_>>();
+        self.inner.steps.push(step);
+        return self.files(sources);
+    }
+
+    // Builds `header` once into `out_dir/pch` and force-includes it in every file.
+    pub fn pch(mut self, header: impl Into<PathBuf>) -> Self {
+        self.inner.pch = Some(PrecompiledHeader {
+            header: header.into().normalize(),
+            out: PathBuf::new(),
+            object: None,
+        });
+        return self;
+    }
 }
 
 impl CompileCommand {
@@ -344,15 +759,29 @@ impl CompileCommand {
         return CompileCommandBuilder::new();
     }
 
-    pub fn get_link_files(&mut self) -> impl IntoIterator<Item = &PathBuf> {
-        return self.files.iter().map(|file| &file.out).collect::<Vec<_>>();
+    pub fn get_link_files(&self) -> impl IntoIterator<Item = &PathBuf> {
+        let pch_object = self.pch.iter().filter_map(|pch| pch.object.as_ref());
+        return self
+            .units()
+            .iter()
+            .map(|file| &file.out)
+            .chain(pch_object)
+            .collect::<Vec<_>>();
+    }
+
+    // The translation units actually handed to the compiler.
+    pub fn units(&self) -> &Vec<CompilationFile> {
+        match &self.unity {
+            Some(unity) => &unity.units,
+            None => &self.files,
+        }
     }
 
     pub fn gen_compiler_commands_json(&mut self) -> String {
         String::new()
     }
 
-    pub fn files(&mut self) -> &Vec<CompilationFile> {
+    pub fn files(&self) -> &Vec<CompilationFile> {
         &self.files
     }
 
@@ -360,287 +789,1016 @@ impl CompileCommand {
         &self.working_directory
     }
 
+    pub fn includes(&self) -> &[PathBuf] {
+        &self.includes
+    }
+
+    pub fn definitions(&self) -> &[String] {
+        &self.definitions
+    }
+
+    pub fn sanitizers(&self) -> &[Sanitizer] {
+        &self.sanitizers
+    }
+
+    pub fn lto_mode(&self) -> Option<Lto> {
+        self.lto
+    }
+
+    pub fn pgo_phase(&self) -> Option<&PgoPhase> {
+        self.pgo.as_ref()
+    }
+
+    // Absolute form of `path`, for flags whose paths have to hold from anywhere.
+    fn absolute(&self, path: &Path) -> PathBuf {
+        match self.working_directory.canonicalize() {
+            Ok(dir) => return dir.join(path).normalize(),
+            Err(_) => return std::path::absolute(self.resolve(path)).unwrap_or_default(),
+        }
+    }
+
+    fn pgo_flags(&self) -> Vec<String> {
+        let (Some(phase), Some(format)) = (&self.pgo, self.toolchain.pgo()) else {
+            return Vec::new();
+        };
+        let dir = self.absolute(phase.dir());
+        let obj_dir = self.absolute(&self.out_dir.join("obj"));
+        match phase {
+            PgoPhase::Generate(_) => return format.generate_flags(&dir, &obj_dir),
+            PgoPhase::Use(_) => return format.use_flags(&dir, &obj_dir),
+        }
+    }
+
+    // The training profiles, which the optimized objects depend on.
+    fn profiles(&self) -> Vec<PathBuf> {
+        let Some(PgoPhase::Use(dir)) = &self.pgo else {
+            return Vec::new();
+        };
+        let Ok(entries) = std::fs::read_dir(self.resolve(dir)) else {
+            return Vec::new();
+        };
+        return entries.flatten().map(|entry| entry.path()).collect();
+    }
+
+    // `None` unless coverage is enabled and the toolchain supports it.
+    pub fn coverage_format(&self) -> Option<CoverageFormat> {
+        if !self.coverage {
+            return None;
+        }
+        return self.toolchain.coverage();
+    }
+
+    // Rejects sanitizers that can't be combined or that `toolchain` can't provide,
+    // using `flags` to ask for the compile or the link side.
+    pub(crate) fn check_sanitizers(
+        &self,
+        toolchain: &dyn Toolchain,
+        flags: impl Fn(&dyn Toolchain, &[Sanitizer]) -> Option<Vec<String>>,
+    ) -> Status {
+        if let Some((first, second)) = sanitize::conflict(&self.sanitizers) {
+            println!(
+                "[ERROR]: the `{}` and `{}` sanitizers can't be combined",
+                first, second
+            );
+            return Status::Aborted;
+        }
+        let unsupported = self
+            .sanitizers
+            .iter()
+            .find(|sanitizer| flags(toolchain, &[**sanitizer]).is_none());
+        if let Some(sanitizer) = unsupported {
+            println!(
+                "[ERROR]: the `{}` sanitizer is not supported by {}",
+                sanitizer,
+                toolchain.name()
+            );
+            return Status::Aborted;
+        }
+        return Status::Success;
+    }
+
     pub fn compiler(&self) -> &str {
-        self.compiler.as_str(self.lang.clone())
+        match &self.toolchain_info {
+            Some(info) => info.compiler(&self.lang),
+            None => self.toolchain.compiler(&self.lang),
+        }
     }
 
-    pub fn args(&mut self) -> String {
-        let mut args = String::new();
-        if let Some(opt_lvl) = &self.optimization_level {
-            args.push_str(", ");
-            args.push_str(&format!(
-                "\"{}\"",
-                match opt_lvl {
-                    OptimizationLevel::O3 => "-O3",
-                    OptimizationLevel::O2 => "-O2",
-                    OptimizationLevel::O1 => "-O1",
-                    OptimizationLevel::O0 => "-O0",
-                    OptimizationLevel::Fast => "-OFast",
-                    OptimizationLevel::Small => "-Os",
-                }
-            ));
+    // What linking this command's objects needs on top of the objects themselves:
+    // sanitizer runtimes, LTO, coverage and PGO instrumentation, spelled by `linker`.
+    pub(crate) fn link_flags(&self, linker: &dyn Toolchain) -> Vec<String> {
+        let mut flags = Vec::new();
+        if !self.sanitizers.is_empty() {
+            flags.extend(linker.sanitize_link(&self.sanitizers).unwrap_or_default());
+        }
+        if let Some(lto) = &self.lto {
+            flags.extend(linker.lto_link(lto));
+        }
+        if let Some(format) = self.coverage_format() {
+            flags.extend(format.link_flags());
         }
+        if let Some(PgoPhase::Generate(_)) = &self.pgo {
+            flags.extend(
+                linker
+                    .pgo()
+                    .map(|format| format.link_flags())
+                    .unwrap_or_default(),
+            );
+        }
+        flags
+    }
 
-        if let Some(debugger) = &self.debug {
-            args.push_str(", ");
-            args.push_str(&format!("\"-g{}\"", debugger));
+    // Links through the detected driver when the toolchain links with its compiler.
+    pub(crate) fn link_driver(&self, bin_type: &BinType) -> &str {
+        let linker = self.toolchain.linker(bin_type);
+        match &self.toolchain_info {
+            Some(info) if linker == self.toolchain.compiler(&Lang::C) => info.compiler(&Lang::C),
+            _ => linker,
         }
-        if let Some(std) = &self.std {
+    }
+
+    pub fn toolchain(&self) -> &dyn Toolchain {
+        self.toolchain.as_ref()
+    }
+
+    pub fn toolchain_info(&self) -> Option<&ToolchainInfo> {
+        self.toolchain_info.as_ref()
+    }
+
+    // Queries the compiler's implicit include directories and macros, cached in `out_dir`.
+    pub fn builtins(&self) -> Option<Builtins> {
+        let probed;
+        let info = match &self.toolchain_info {
+            Some(info) => info,
+            None => {
+                probed = ToolchainInfo::probe(find_in_path(self.compiler())?)?;
+                &probed
+            }
+        };
+        return Builtins::query(info, &self.lang, &self.out_dir);
+    }
+
+    pub fn args(&self) -> String {
+        let mut args = String::new();
+        for flag in self.flags() {
             args.push_str(", ");
-            args.push_str(&format!(
-                "\"{}\"",
-                match std {
-                    Std::C99 => "-std=c99",
-                    Std::C11 => "-std=c11",
-                }
-            ));
+            args.push_str(&crate::generator::json_str(&flag));
         }
-        if let Some(_taget) = &self.target {
-            args.push_str(", ");
-            todo!();
+        args
+    }
+
+    // Paths handed to the tools are relative to the working directory, not to our own.
+    pub(crate) fn resolve(&self, path: &Path) -> PathBuf {
+        return self.working_directory.join(path);
+    }
+
+    pub fn launcher(&self) -> Option<&Launcher> {
+        self.launcher.as_ref()
+    }
+
+    // Where the compiler records the headers a unit read, if the toolchain writes depfiles.
+    // Arguments that make the tool for `kind` report the headers it reads, and the
+    // format of that report (see `Toolchain::deps_format`).
+    pub(crate) fn depfile_args(&self, kind: SourceKind, dep: &Path) -> Option<(Vec<String>, &str)> {
+        match kind {
+            SourceKind::C | SourceKind::PreprocessedAssembly => {
+                return Some((self.toolchain.depfile(dep), self.toolchain.deps_format()))
+            }
+            SourceKind::Nasm => return Some((self.assembler.depfile(dep)?, "gcc")),
+            SourceKind::Assembly => return None,
         }
+    }
 
-        for include in &self.includes {
-            args.push_str(", ");
-            args.push_str(&format!("\"-I\", {:?}", include));
+    fn depfile_path(&self, file: &CompilationFile) -> Option<PathBuf> {
+        return self.depfile_for_kind(file, SourceKind::of(&file.src));
+    }
+
+    // `<out>.d`, so generators can spell it `$out.d` from the placeholder output.
+    fn depfile_for_kind(&self, file: &CompilationFile, kind: SourceKind) -> Option<PathBuf> {
+        let mut dep = file.out.clone().into_os_string();
+        dep.push(".d");
+        let dep = PathBuf::from(dep);
+        match self.depfile_args(kind, &dep) {
+            Some((_, "gcc")) => return Some(dep),
+            _ => return None,
         }
+    }
 
-        for define in &self.definitions {
-            args.push_str(", ");
-            args.push_str(&format!("\"-D{}\"", define));
+    // What the compiler reported reading for `file` on its last compile.
+    fn recorded_deps(&self, file: &CompilationFile) -> Vec<PathBuf> {
+        let Some(depfile) = self.depfile_path(file) else {
+            return Vec::new();
+        };
+        match std::fs::read_to_string(self.resolve(&depfile)) {
+            Ok(content) => return parse_depfile(&content),
+            Err(_) => return Vec::new(),
         }
+    }
 
-        args
+    // Headers read by the last compile of each unit, as reported through depfiles.
+    pub fn headers(&self) -> Vec<PathBuf> {
+        let mut headers: Vec<PathBuf> = Vec::new();
+        for unit in self.units() {
+            for dep in self.recorded_deps(unit) {
+                let is_source = dep == unit.src
+                    || unit.deps.contains(&dep)
+                    || self.files.iter().any(|file| file.src == dep);
+                if !is_source && !headers.contains(&dep) {
+                    headers.push(dep);
+                }
+            }
+        }
+        headers
     }
 
     pub fn get_modified_files(&self) -> impl IntoIterator<Item = &CompilationFile> {
-        self.files
+        let pch = self.pch.as_ref().map(|pch| self.resolve(&pch.out));
+        let flags_changed = self.flags_changed();
+        let profiles = self.profiles();
+        self.units()
             .iter()
-            .filter_map(|file| {
-                let (src, out) = (&file.src, &file.out);
-                if !out.exists() {
-                    return Some(file);
-                }
-                match (src.metadata(), out.metadata()) {
-                    (Ok(src), Ok(out)) => match (src.modified(), out.modified()) {
-                        (Ok(src), Ok(out)) => {
-                            if out < src {
-                                return Some(file);
-                            } else {
-                                return None;
-                            }
-                        }
-                        _ => return Some(file),
-                    },
-                    _ => return Some(file),
+            .filter(|file| {
+                if flags_changed {
+                    return true;
                 }
+                let inputs = std::iter::once(&file.src)
+                    .chain(&file.deps)
+                    .cloned()
+                    .chain(self.recorded_deps(file))
+                    .map(|input| self.resolve(&input))
+                    .chain(pch.clone())
+                    .chain(profiles.iter().cloned());
+                is_outdated(&self.resolve(&file.out), inputs)
             })
             .collect::<Vec<_>>()
     }
 
-    fn src_file(&self, file: &CompilationFile) -> Vec<String> {
-        match self.compiler {
-            Compiler::Clang | Compiler::Gcc => {
-                return Vec::from([
-                    "-c".into(),
-                    file.src.display().to_string(),
-                    "-o".into(),
-                    file.out.display().to_string(),
-                ]);
-            }
-            Compiler::Msvc => {
-                return Vec::from([
-                    "/c".into(),
-                    file.src.display().to_string(),
-                    format!("/Fo{}", file.out.display().to_string()),
-                ])
-            }
-        }
-    }
-
-    fn opt_level(&self) -> Option<&str> {
-        if let Some(opt_level) = &self.optimization_level {
-            match self.compiler {
-                Compiler::Clang | Compiler::Gcc => match opt_level {
-                    OptimizationLevel::O3 => return Some("-O3"),
-                    OptimizationLevel::O2 => return Some("-O2"),
-                    OptimizationLevel::O1 => return Some("-O1"),
-                    OptimizationLevel::O0 => return Some("-O0"),
-                    OptimizationLevel::Fast => return Some("-OFast"),
-                    OptimizationLevel::Small => return Some("-Os"),
-                },
-                Compiler::Msvc => match opt_level {
-                    OptimizationLevel::O3 => return Some("/O2"),
-                    OptimizationLevel::O2 => return Some("/O2"),
-                    OptimizationLevel::O1 => return Some("/O1"),
-                    OptimizationLevel::O0 => return Some("/Od"),
-                    OptimizationLevel::Fast => return Some("/Ot"),
-                    OptimizationLevel::Small => return Some("/Os"),
-                },
-            }
-        }
-        return None;
-    }
-
-    fn debuger(&self) -> Option<&str> {
-        if let Some(debugger) = &self.debug {
-            return Some(debugger.as_str());
-        }
-        return None;
-    }
-
-    fn lang_std(&self) -> Option<&str> {
-        if let Some(std) = &self.std {
-            match self.compiler {
-                Compiler::Clang | Compiler::Gcc => match std {
-                    Std::C99 => return Some("-std=c99"),
-                    Std::C11 => return Some("-std=c11"),
-                },
-                Compiler::Msvc => match self.std.as_ref()? {
-                    Std::C99 => {
-                        let _guard = self.tracing.enter();
-                        event!(Level::WARN, "failed to set language standard");
-                        return None;
-                    }
-                    Std::C11 => return Some("/std:c11"),
-                },
-            }
+    fn opt_level(&self) -> Option<String> {
+        return self.toolchain.opt_level(self.optimization_level.as_ref()?);
+    }
+
+    fn debuger(&self) -> Option<String> {
+        match self.debug.as_ref()? {
+            Debug::Flag(flag) => return Some(flag.clone()),
+            Debug::Info(info) => return self.toolchain.debug(info),
         }
-        None
     }
 
-    fn compilation_target(&self) -> Option<&str> {
-        if let Some(target) = &self.target {
-            match self.compiler {
-                Compiler::Clang => match target {
-                    Target::X86_64 => return Some("--taget=x86-64"),
-                },
-                Compiler::Msvc | Compiler::Gcc => {
-                    let _guard = self.tracing.enter();
-                    event!(Level::WARN, "unsupported target `{:?}`", target);
-                    return None;
-                }
+    fn lang_std(&self) -> Option<String> {
+        let std = self.std.as_ref()?;
+        if let Some(info) = &self.toolchain_info {
+            if !info.supports_std(std) {
+                let _guard = self.tracing.enter();
+                event!(
+                    Level::WARN,
+                    "`{:?}` is not supported by {} {}",
+                    std,
+                    info.program().display(),
+                    info.version()
+                );
+                return None;
             }
         }
-        None
+        let flag = self.toolchain.lang_std(std);
+        if flag.is_none() {
+            let _guard = self.tracing.enter();
+            event!(Level::WARN, "failed to set language standard `{:?}`", std);
+        }
+        flag
     }
 
-    fn include(&self, file: &Path) -> Vec<String> {
-        match self.compiler {
-            Compiler::Clang | Compiler::Gcc => {
-                return Vec::from(["-I".into(), file.display().to_string()])
-            }
-            Compiler::Msvc => return Vec::from(["/I".into(), file.display().to_string()]),
+    fn compilation_target(&self) -> Option<String> {
+        let target = self.target.as_ref()?;
+        let flag = self.toolchain.target(target);
+        if flag.is_none() {
+            let _guard = self.tracing.enter();
+            event!(Level::WARN, "unsupported target `{:?}`", target);
         }
+        flag
     }
 
-    fn definition(&self, def: &str) -> String {
-        match self.compiler {
-            Compiler::Clang | Compiler::Gcc => {
-                return format!("-D{}", def);
-            }
-            Compiler::Msvc => {
-                return format!("/D{}", def);
+    // Assembly gets what the preprocessor and assembler understand; the language
+    // standard, optimization and PIC flags only concern C.
+    fn asm_flags(&self, kind: SourceKind) -> Vec<String> {
+        let mut flags = Vec::new();
+        flags.extend(self.debuger());
+        flags.extend(self.compilation_target());
+        for include in &self.includes {
+            flags.extend(self.toolchain.include(include));
+        }
+        if kind == SourceKind::PreprocessedAssembly {
+            for def in &self.definitions {
+                flags.push(self.toolchain.definition(def));
             }
         }
+        flags
+    }
+
+    // The detected compiler's target triple; empty (the host) without detection.
+    fn target_triple(&self) -> &str {
+        self.toolchain_info
+            .as_ref()
+            .map(|info| info.target())
+            .unwrap_or_default()
     }
 
-    fn build_command_for_file(&self, file: &CompilationFile) -> Command {
-        let mut cmd = Command::new(self.compiler.as_str(self.lang.clone()));
-        cmd.current_dir(&self.working_directory);
-        if self.compiler == Compiler::Msvc {
-            cmd.arg("/nologo");
+    fn nasm_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if self.debug.is_some() {
+            flags.extend(self.assembler.debug(self.target_triple()));
         }
-        for part in self.src_file(file) {
-            cmd.arg(part);
+        for include in &self.includes {
+            flags.push(self.assembler.include(include));
+        }
+        for def in &self.definitions {
+            flags.push(self.assembler.definition(def));
         }
-        if let Some(opt) = self.opt_level() {
-            cmd.arg(opt);
+        flags
+    }
+
+    pub(crate) fn flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        flags.extend(self.opt_level());
+        flags.extend(self.debuger());
+        flags.extend(self.lang_std());
+        flags.extend(self.compilation_target());
+        if self.pic {
+            flags.extend(self.toolchain.pic());
         }
-        if let Some(debugger) = self.debuger() {
-            cmd.arg(debugger);
+        if !self.sanitizers.is_empty() {
+            flags.extend(
+                self.toolchain
+                    .sanitize(&self.sanitizers)
+                    .unwrap_or_default(),
+            );
         }
-        if let Some(std) = self.lang_std() {
-            cmd.arg(std);
+        if let Some(lto) = &self.lto {
+            flags.extend(self.toolchain.lto(lto));
         }
-        if let Some(target) = self.compilation_target() {
-            cmd.arg(target);
+        if let Some(format) = self.coverage_format() {
+            flags.extend(format.compile_flags());
         }
+        flags.extend(self.pgo_flags());
         for include in &self.includes {
-            let include = self.include(include.as_path());
-            for part in include {
-                cmd.arg(part);
-            }
+            flags.extend(self.toolchain.include(include));
         }
         for def in &self.definitions {
-            let def = self.definition(def);
-            cmd.arg(def);
+            flags.push(self.toolchain.definition(def));
+        }
+        flags.extend(self.extra_flags.iter().cloned());
+        flags
+    }
+
+    // Exactly what `run` executes for `file`, depfile arguments included.
+    pub fn build_command_for_file(&self, file: &CompilationFile) -> Invocation {
+        return self.build_command_for_kind(file, SourceKind::of(&file.src));
+    }
+
+    pub(crate) fn build_command_for_kind(
+        &self,
+        file: &CompilationFile,
+        kind: SourceKind,
+    ) -> Invocation {
+        let mut cmd = self.raw_command_for_kind(file, kind);
+        if let Some(depfile) = self.depfile_for_kind(file, kind) {
+            if let Some((args, _)) = self.depfile_args(kind, &depfile) {
+                cmd = cmd.args(args);
+            }
+        }
+        match &self.launcher {
+            // Compiler caches only understand compiler command lines.
+            Some(_) if kind == SourceKind::Nasm => return cmd,
+            Some(launcher) => return cmd.wrap(launcher.program(), Vec::<String>::new()),
+            None => return cmd,
+        }
+    }
+
+    // The compiler invocation without any launcher, as recorded in compilation databases.
+    pub fn raw_command_for_file(&self, file: &CompilationFile) -> Invocation {
+        return self.raw_command_for_kind(file, SourceKind::of(&file.src));
+    }
+
+    // Like `raw_command_for_file`, for generators whose file names are placeholders.
+    pub(crate) fn raw_command_for_kind(
+        &self,
+        file: &CompilationFile,
+        kind: SourceKind,
+    ) -> Invocation {
+        if kind == SourceKind::Nasm {
+            return Invocation::new(self.assembler.program())
+                .cwd(&self.working_directory)
+                .input(&file.src)
+                .output(&file.out)
+                .args(
+                    self.assembler
+                        .src_file(&file.src, &file.out, self.target_triple()),
+                )
+                .args(self.nasm_flags());
+        }
+        let cmd = Invocation::new(self.compiler())
+            .cwd(&self.working_directory)
+            .input(&file.src)
+            .output(&file.out)
+            .args(self.toolchain.compile_preamble())
+            .args(self.toolchain.src_file(&file.src, &file.out));
+        if !kind.is_c() {
+            return cmd.args(self.asm_flags(kind));
+        }
+        let mut cmd = cmd.args(self.flags());
+        if let Some(pch) = &self.pch {
+            cmd = cmd
+                .input(&pch.out)
+                .args(self.toolchain.pch_use(&pch.header, &pch.out));
         }
         cmd
     }
 
+    pub fn pch_header(&self) -> Option<&Path> {
+        return self.pch.as_ref().map(|pch| pch.header.as_path());
+    }
+
+    pub fn pch_invocation(&self) -> Option<Invocation> {
+        let pch = self.pch.as_ref()?;
+        let mut cmd = Invocation::new(self.compiler())
+            .cwd(&self.working_directory)
+            .input(&pch.header)
+            .output(&pch.out)
+            .args(self.toolchain.compile_preamble())
+            .args(self.toolchain.pch_create(&pch.header, &pch.out, &self.lang))
+            .args(self.flags());
+        if let Some(object) = &pch.object {
+            cmd = cmd.output(object);
+        }
+        if let Some(depfile) = self.pch_depfile() {
+            cmd = cmd.args(self.toolchain.depfile(&depfile));
+        }
+        return Some(cmd);
+    }
+
+    // Records the headers the precompiled header pulls in, like a unit's depfile.
+    fn pch_depfile(&self) -> Option<PathBuf> {
+        let pch = self.pch.as_ref()?;
+        let mut dep = pch.out.clone().into_os_string();
+        dep.push(".d");
+        let dep = PathBuf::from(dep);
+        match self.depfile_args(SourceKind::C, &dep) {
+            Some((_, "gcc")) => Some(dep),
+            _ => None,
+        }
+    }
+
+    pub fn invocations(&self) -> Vec<Invocation> {
+        return self
+            .units()
+            .iter()
+            .map(|file| self.build_command_for_file(file))
+            .collect();
+    }
+
     fn try_create_out_dir(&self) {
-        let out_dir = self.out_dir.join("obj");
-        if out_dir.exists() {
-            return;
+        let out_dir = self.resolve(&self.out_dir.join("obj"));
+        if !out_dir.exists() {
+            let _guard = self.tracing.enter();
+            event!(
+                Level::INFO,
+                "creating out directory: `{}`",
+                out_dir.display()
+            );
+            if let Err(e) = std::fs::create_dir_all(&out_dir) {
+                event!(Level::WARN, "failed to create out dir: `{}`", e);
+            }
+            for dir in &self.dirs {
+                let out = out_dir.join(dir);
+                std::fs::create_dir_all(out).unwrap();
+            }
         }
+        for unit in self.units() {
+            if let Some(dir) = self.resolve(&unit.out).parent() {
+                if !dir.exists() {
+                    if let Err(e) = std::fs::create_dir_all(dir) {
+                        event!(Level::WARN, "failed to create out dir: `{}`", e);
+                    }
+                }
+            }
+        }
+    }
 
-        let _guard = self.tracing.enter();
-        event!(
-            Level::INFO,
-            "creating out directory: `{}`",
-            out_dir.display()
-        );
-        if let Err(e) = std::fs::create_dir_all(&out_dir) {
-            event!(Level::WARN, "failed to create out dir: `{}`", e);
+    pub fn out_dir(&self) -> &PathBuf {
+        return &self.out_dir;
+    }
+
+    // Runs `invocation`, returning false if the tool ran and reported failure.
+    fn execute(&self, invocation: &Invocation) -> bool {
+        use std::process::Stdio;
+        event!(Level::DEBUG, "executing: {}", invocation);
+        let mut cmd = invocation.to_command();
+        cmd.stdout(Stdio::inherit());
+        cmd.stderr(Stdio::inherit());
+        match cmd.output() {
+            Ok(out) => return out.status.success(),
+            // A tool that never ran produced nothing, so nothing may be cached or used.
+            Err(e) => {
+                println!(
+                    "[ERROR]: failed to run `{}`: `{}`",
+                    invocation.get_program(),
+                    e
+                );
+                return false;
+            }
         }
-        for dir in &self.dirs {
-            let out = out_dir.join(dir);
-            std::fs::create_dir_all(out).unwrap();
+    }
+
+    // Writes `header` to `out_dir/include` and adds that directory to the include
+    // paths. An unchanged header is left alone so nothing including it is rebuilt.
+    pub fn config_header(&mut self, header: &ConfigHeader) -> std::io::Result<PathBuf> {
+        let include_dir = self.out_dir.join("include").normalize();
+        let path = include_dir.join(header.name());
+        let content = header.render(&self.working_directory)?;
+        let resolved = self.resolve(&path);
+        if let Some(parent) = resolved.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        write_if_changed(&resolved, &content)?;
+        if !self.includes.contains(&include_dir) {
+            self.includes.push(include_dir);
+        }
+        return Ok(path);
     }
 
-    fn normalize_paths(&mut self) {
-        let out_dir = self.out_dir.join("obj");
-        self.files.iter_mut().for_each(|file| {
-            file.prepend_outdir(&out_dir);
-            if self.compiler == Compiler::Msvc {
-                file.out.set_extension("obj");
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
+
+    pub fn steps(&self) -> &[CustomStep] {
+        &self.steps
+    }
+
+    // True if `path` is written by a custom step or the resource embedding.
+    pub fn is_generated(&self, path: &Path) -> bool {
+        if path.starts_with(self.out_dir.join("embed").normalize()) {
+            return true;
+        }
+        return self
+            .steps
+            .iter()
+            .any(|step| step.outputs().iter().any(|output| output == path));
+    }
+
+    fn run_steps(&self) -> Status {
+        let Some(steps) = step::schedule(&self.steps) else {
+            println!("[ERROR]: custom steps depend on each other in a cycle");
+            return Status::Aborted;
+        };
+        for step in steps {
+            if !step.is_outdated(|path| self.resolve(path)) {
+                continue;
             }
-        });
-        self.includes
-            .iter_mut()
-            .for_each(|file| *file = file.normalize());
+            for output in step.outputs() {
+                if let Some(dir) = self.resolve(output).parent() {
+                    if let Err(e) = std::fs::create_dir_all(dir) {
+                        event!(Level::WARN, "failed to create out dir: `{}`", e);
+                    }
+                }
+            }
+            let cwd = self.resolve(step.invocation().get_cwd());
+            println!("[Running]: {}", step.name());
+            if !self.execute(&step.invocation().clone().cwd(cwd)) {
+                println!(
+                    "[ERROR]: step `{}` failed; compilation aborted",
+                    step.name()
+                );
+                return Status::Aborted;
+            }
+        }
+        return Status::Success;
     }
 
-    pub fn out_dir(&mut self) -> &PathBuf {
-        return &self.out_dir;
+    // Sources cbuild writes itself; needed before anything compiles them.
+    pub(crate) fn write_generated_sources(&self) {
+        self.write_resources();
+        self.write_unity_sources();
+    }
+
+    // Resources named alike, e.g. `a/icon.png` and `b/icon.png`, would share a source
+    // file and a symbol.
+    fn duplicate_symbol(&self) -> Option<&str> {
+        self.resources.iter().enumerate().find_map(|(i, resource)| {
+            self.resources[..i]
+                .iter()
+                .any(|other| other.symbol() == resource.symbol())
+                .then_some(resource.symbol())
+        })
+    }
+
+    // Regenerates a resource's source and header when the resource is newer, writing
+    // only what actually changed.
+    fn write_resources(&self) {
+        if let Some(symbol) = self.duplicate_symbol() {
+            event!(
+                Level::ERROR,
+                "more than one resource is embedded as `{}`; not writing embedded sources",
+                symbol
+            );
+            return;
+        }
+        let embed_dir = self.out_dir.join("embed").normalize();
+        for resource in &self.resources {
+            let src = self.resolve(&embed_dir.join(format!("{}.c", resource.symbol())));
+            let header = self.resolve(&embed_dir.join(resource.header_name()));
+            let input = self.resolve(resource.path());
+            if !is_outdated(&src, [&input]) && header.exists() {
+                continue;
+            }
+            let input = std::path::absolute(&input).unwrap_or(input);
+            let written = std::fs::create_dir_all(self.resolve(&embed_dir))
+                .and_then(|_| write_if_changed(&header, &resource.header()))
+                .and_then(|_| resource.source(self.embed_mode, &input))
+                .and_then(|source| write_if_changed(&src, &source));
+            if let Err(e) = written {
+                event!(
+                    Level::WARN,
+                    "failed to embed `{}`: `{}`",
+                    resource.path().display(),
+                    e
+                );
+            }
+        }
+    }
+
+    // Writes the batch sources, leaving unchanged ones alone so their objects stay fresh.
+    fn write_unity_sources(&self) {
+        let Some(unity) = &self.unity else {
+            return;
+        };
+        for unit in &unity.units {
+            if unit.deps.is_empty() {
+                continue;
+            }
+            let mut content = String::new();
+            for member in &unit.deps {
+                let member = std::path::absolute(self.resolve(member)).unwrap_or(member.clone());
+                content.push_str(&format!("#include \"{}\"\n", member.display()));
+            }
+            let src = self.resolve(&unit.src);
+            if std::fs::read_to_string(&src).ok().as_deref() == Some(content.as_str()) {
+                continue;
+            }
+            let written = std::fs::create_dir_all(src.parent().unwrap_or(Path::new(".")))
+                .and_then(|_| std::fs::write(&src, content));
+            if let Err(e) = written {
+                event!(Level::WARN, "failed to write unity source: `{}`", e);
+            }
+        }
+    }
+
+    fn build_pch(&self) -> Status {
+        let Some(pch) = &self.pch else {
+            return Status::Success;
+        };
+        let out = self.resolve(&pch.out);
+        let recorded = self
+            .pch_depfile()
+            .and_then(|depfile| std::fs::read_to_string(self.resolve(&depfile)).ok())
+            .map(|content| parse_depfile(&content))
+            .unwrap_or_default();
+        let inputs = std::iter::once(&pch.header)
+            .chain(&recorded)
+            .map(|input| self.resolve(input));
+        if !self.flags_changed() && !is_outdated(&out, inputs) {
+            return Status::Success;
+        }
+        if let Some(dir) = out.parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                event!(Level::WARN, "failed to create pch dir: `{}`", e);
+            }
+        }
+        println!("[Precompiling]: {}", pch.header.display());
+        if let Some(invocation) = self.pch_invocation() {
+            if !self.execute(&invocation) {
+                println!(
+                    "[ERROR]: failed to precompile `{}`; compilation aborted",
+                    pch.header.display()
+                );
+                return Status::Aborted;
+            }
+        }
+        return Status::Success;
     }
 
     pub fn run(&mut self) -> Status {
-        use std::process::Stdio;
         let _guard = self.tracing.enter();
+        if let Status::Aborted = self.check_sanitizers(self.toolchain(), |toolchain, sanitizers| {
+            toolchain.sanitize(sanitizers)
+        }) {
+            return Status::Aborted;
+        }
+        if let Some(lto) = &self.lto {
+            if self.toolchain.lto(lto).is_none() {
+                println!(
+                    "[ERROR]: `{:?}` LTO is not supported by {}",
+                    lto,
+                    self.toolchain.name()
+                );
+                return Status::Aborted;
+            }
+        }
+        if self.pgo.is_some() && self.toolchain.pgo().is_none() {
+            println!(
+                "[ERROR]: profile-guided optimization is not supported by {}",
+                self.toolchain.name()
+            );
+            return Status::Aborted;
+        }
+        if self.coverage && self.toolchain.coverage().is_none() {
+            println!(
+                "[ERROR]: coverage is not supported by {}",
+                self.toolchain.name()
+            );
+            return Status::Aborted;
+        }
+        if !self.toolchain.assembles_gas() {
+            let gas = self.units().iter().find(|unit| {
+                matches!(
+                    SourceKind::of(&unit.src),
+                    SourceKind::Assembly | SourceKind::PreprocessedAssembly
+                )
+            });
+            if let Some(unit) = gas {
+                println!(
+                    "[ERROR]: {} can't assemble `{}`; port it to NASM syntax (`.asm`)",
+                    self.toolchain.name(),
+                    unit.src.display()
+                );
+                return Status::Aborted;
+            }
+        }
+        if let Some(symbol) = self.duplicate_symbol() {
+            println!(
+                "[ERROR]: more than one resource is embedded as `{}`; rename one with `embed_as`",
+                symbol
+            );
+            return Status::Aborted;
+        }
         self.try_create_out_dir();
-        for file in self.get_modified_files() {
-            let mut cmd = self.build_command_for_file(file);
-            println!("[Compiling]: {}", file);
-            event!(Level::DEBUG, "executing: {:?}", cmd);
-            cmd.stdout(Stdio::inherit());
-            cmd.stderr(Stdio::inherit());
-            match cmd.output() {
-                Ok(out) => {
-                    if !out.status.success() {
-                        println!(
-                            "[ERROR]: failed to compile `{}`; compilation aborted",
-                            file.src.display()
-                        );
-                        return Status::Aborted;
-                    }
+        if let Status::Aborted = self.run_steps() {
+            return Status::Aborted;
+        }
+        self.write_generated_sources();
+        if let Status::Aborted = self.build_pch() {
+            return Status::Aborted;
+        }
+        let stats = self.launcher.as_ref().and_then(|launcher| launcher.stats());
+        let status = self.compile_modified();
+        self.evict_cached();
+        if let (Some(launcher), Some(before)) = (&self.launcher, stats) {
+            if let Some(after) = launcher.stats() {
+                println!("[Cache]: {}", after.since(&before));
+            }
+        }
+        if let Status::Success = status {
+            if let Err(e) = std::fs::write(self.fingerprint_file(), self.flags_fingerprint()) {
+                event!(Level::WARN, "failed to write flags fingerprint: `{}`", e);
+            }
+        }
+        return status;
+    }
+
+    // Hash of the compiler and the flags every unit shares, such as the LTO mode.
+    fn flags_fingerprint(&self) -> String {
+        let mut hasher = Hasher::new();
+        hasher.field(self.compiler().as_bytes());
+        for flag in self.flags() {
+            hasher.field(flag.as_bytes());
+        }
+        return hasher.finish();
+    }
+
+    fn fingerprint_file(&self) -> PathBuf {
+        return self.resolve(&self.out_dir.join("obj").join("flags.fingerprint"));
+    }
+
+    // Objects built with other flags are stale no matter their timestamps.
+    fn flags_changed(&self) -> bool {
+        match std::fs::read_to_string(self.fingerprint_file()) {
+            Ok(old) => return old != self.flags_fingerprint(),
+            Err(_) => return true,
+        }
+    }
+
+    // Identifies the compiler binary so cache entries never cross toolchain versions.
+    pub fn toolchain_fingerprint(&self) -> String {
+        if let Some(info) = &self.toolchain_info {
+            return info.fingerprint();
+        }
+        let probed = find_in_path(self.compiler()).and_then(ToolchainInfo::probe);
+        match probed {
+            Some(info) => return info.fingerprint(),
+            None => return self.toolchain.name().to_string(),
+        }
+    }
+
+    // `None` for plain `.s` files, which have no preprocessing step.
+    pub fn preprocess_invocation(&self, file: &CompilationFile) -> Option<Invocation> {
+        let kind = SourceKind::of(&file.src);
+        match kind {
+            SourceKind::Nasm => {
+                return Some(
+                    Invocation::new(self.assembler.program())
+                        .cwd(&self.working_directory)
+                        .input(&file.src)
+                        .args(self.assembler.preprocess(&file.src))
+                        .args(self.nasm_flags()),
+                )
+            }
+            SourceKind::Assembly => return None,
+            _ => {}
+        }
+        let flags = match kind {
+            SourceKind::C => self.flags(),
+            _ => self.asm_flags(kind),
+        };
+        return Some(
+            Invocation::new(self.compiler())
+                .cwd(&self.working_directory)
+                .input(&file.src)
+                .args(self.toolchain.compile_preamble())
+                .args(self.toolchain.preprocess(&file.src))
+                .args(flags),
+        );
+    }
+
+    // Hash of the preprocessed source, the compile arguments with the file's own
+    // paths masked out, the toolchain fingerprint and the contents of the file's deps and of the PGO profiles.
+    pub fn cache_key(&self, file: &CompilationFile, fingerprint: &str) -> Option<String> {
+        let source = match self.preprocess_invocation(file) {
+            Some(invocation) => {
+                let out = invocation.to_command().output().ok()?;
+                if !out.status.success() {
+                    return None;
                 }
-                Err(e) => {
-                    event!(Level::WARN, "error occured: `{}`", e);
+                out.stdout
+            }
+            None => std::fs::read(self.resolve(&file.src)).ok()?,
+        };
+        let mut hasher = Hasher::new();
+        hasher.field(fingerprint.as_bytes());
+        hasher.field(&source);
+        let (src, obj) = (
+            file.src.display().to_string(),
+            file.out.display().to_string(),
+        );
+        let raw = self.raw_command_for_file(file);
+        hasher.field(raw.get_program().as_bytes());
+        for arg in raw.get_args() {
+            hasher.field(arg.replace(&obj, "<out>").replace(&src, "<src>").as_bytes());
+        }
+        if let Some(pch) = &self.pch {
+            hasher.field(&std::fs::read(self.resolve(&pch.header)).ok()?);
+        }
+        // Files pulled in outside the preprocessor, such as `.incbin` resources.
+        for dep in file.deps() {
+            hasher.field(&std::fs::read(self.resolve(dep)).ok()?);
+        }
+        // The profile data steers optimization without appearing in the arguments.
+        let mut profiles = self.profiles();
+        profiles.sort();
+        for profile in profiles {
+            hasher.field(profile.file_name().unwrap_or_default().as_encoded_bytes());
+            hasher.field(&std::fs::read(profile).ok()?);
+        }
+        return Some(hasher.finish());
+    }
+
+    pub(crate) fn has_cache(&self) -> bool {
+        return self.cache.is_some() || self.remote_cache.is_some();
+    }
+
+    // Fills `out` from the local cache, then the remote one; remote hits are kept locally.
+    // Entries are stored as plain bytes, so `executable` outputs get their mode back here.
+    pub(crate) fn restore_cached(&self, key: &str, out: &Path, executable: bool) -> bool {
+        if !self.restore_entry(key, out) {
+            return false;
+        }
+        #[cfg(unix)]
+        if executable {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(0o755);
+            if let Err(e) = std::fs::set_permissions(out, permissions) {
+                event!(
+                    Level::WARN,
+                    "failed to make `{}` executable: `{}`",
+                    out.display(),
+                    e
+                );
+                return false;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = executable;
+        true
+    }
+
+    fn restore_entry(&self, key: &str, out: &Path) -> bool {
+        if let Some(cache) = &self.cache {
+            if cache.get(key, out) {
+                return true;
+            }
+        }
+        let Some(data) = self
+            .remote_cache
+            .as_ref()
+            .and_then(|remote| remote.get(key))
+        else {
+            return false;
+        };
+        if let Err(e) = std::fs::write(out, &data) {
+            event!(
+                Level::WARN,
+                "failed to restore `{}`: `{}`",
+                out.display(),
+                e
+            );
+            return false;
+        }
+        if let Some(cache) = &self.cache {
+            cache.put_bytes(key, &data);
+        }
+        return true;
+    }
+
+    // Trims the local cache back to its size limit after a step stored its outputs.
+    pub(crate) fn evict_cached(&self) {
+        if let Some(cache) = &self.cache {
+            cache.evict();
+        }
+    }
+
+    pub(crate) fn store_cached(&self, key: &str, out: &Path) {
+        if let Some(cache) = &self.cache {
+            cache.put(key, out);
+        }
+        if let Some(remote) = self.remote_cache.as_ref().filter(|r| r.is_writable()) {
+            if let Ok(data) = std::fs::read(out) {
+                remote.put(key, &data);
+            }
+        }
+    }
+
+    // Files a compile writes next to the object: the depfile and gcc's coverage notes.
+    // They're cached with the object, keyed by their extension.
+    fn side_outputs(&self, file: &CompilationFile) -> Vec<PathBuf> {
+        let mut outputs = Vec::from_iter(self.depfile_path(file));
+        if self.coverage_format() == Some(CoverageFormat::Gcov) {
+            outputs.push(file.out.with_extension("gcno"));
+        }
+        outputs
+    }
+
+    fn side_key(key: &str, output: &Path) -> String {
+        let ext = output.extension().unwrap_or_default().to_string_lossy();
+        format!("{}-{}", key, ext)
+    }
+
+    // An object restored without its side outputs would lose its recorded headers
+    // or coverage notes, so either all of them come back or the file is compiled.
+    fn restore_compiled(&self, key: &str, file: &CompilationFile) -> bool {
+        self.restore_cached(key, &self.resolve(&file.out), false)
+            && self.side_outputs(file).iter().all(|output| {
+                self.restore_cached(&Self::side_key(key, output), &self.resolve(output), false)
+            })
+    }
+
+    fn store_compiled(&self, key: &str, file: &CompilationFile) {
+        self.store_cached(key, &self.resolve(&file.out));
+        for output in self.side_outputs(file) {
+            self.store_cached(&Self::side_key(key, &output), &self.resolve(&output));
+        }
+    }
+
+    fn compile_modified(&self) -> Status {
+        let fingerprint = self.has_cache().then(|| self.toolchain_fingerprint());
+        for file in self.get_modified_files() {
+            let key = fingerprint
+                .as_ref()
+                .and_then(|fingerprint| self.cache_key(file, fingerprint));
+            if let Some(key) = &key {
+                if self.restore_compiled(key, file) {
+                    println!("[Cached]: {}", file);
+                    continue;
                 }
             }
+            let invocation = self.build_command_for_file(file);
+            println!("[Compiling]: {}", file);
+            if !self.execute(&invocation) {
+                println!(
+                    "[ERROR]: failed to compile `{}`; compilation aborted",
+                    file.src.display()
+                );
+                return Status::Aborted;
+            }
+            if let Some(key) = &key {
+                self.store_compiled(key, file);
+            }
         }
         return Status::Success;
     }
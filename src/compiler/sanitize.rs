@@ -0,0 +1,39 @@
+// Runtime checkers instrumented in at compile time and linked in at link time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sanitizer {
+    Address,
+    Undefined,
+    Thread,
+    // Clang only.
+    Memory,
+}
+
+impl Sanitizer {
+    // Name as spelled in `-fsanitize=`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sanitizer::Address => return "address",
+            Sanitizer::Undefined => return "undefined",
+            Sanitizer::Thread => return "thread",
+            Sanitizer::Memory => return "memory",
+        }
+    }
+}
+
+impl std::fmt::Display for Sanitizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// ASan, TSan and MSan each take over the shadow memory, so at most one of them fits.
+pub(crate) fn conflict(sanitizers: &[Sanitizer]) -> Option<(Sanitizer, Sanitizer)> {
+    let exclusive = sanitizers
+        .iter()
+        .filter(|sanitizer| **sanitizer != Sanitizer::Undefined)
+        .collect::<Vec<_>>();
+    match exclusive.as_slice() {
+        [first, second, ..] => return Some((**first, **second)),
+        _ => return None,
+    }
+}
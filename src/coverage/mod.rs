@@ -0,0 +1,435 @@
+use crate::compiler::{CompileCommand, SourceKind};
+use crate::invocation::Invocation;
+use crate::json::Json;
+use crate::path::Normalize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+// How instrumented code records what ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageFormat {
+    // `--coverage`: `.gcda` counters next to the objects, read back with `gcov`.
+    Gcov,
+    // Source-based coverage: `.profraw` files merged by `llvm-profdata`, read with `llvm-cov`.
+    Llvm,
+}
+
+impl CoverageFormat {
+    pub fn compile_flags(&self) -> Vec<String> {
+        match self {
+            CoverageFormat::Gcov => return Vec::from(["--coverage".into()]),
+            CoverageFormat::Llvm => {
+                return Vec::from([
+                    "-fprofile-instr-generate".into(),
+                    "-fcoverage-mapping".into(),
+                ])
+            }
+        }
+    }
+
+    pub fn link_flags(&self) -> Vec<String> {
+        match self {
+            CoverageFormat::Gcov => return Vec::from(["--coverage".into()]),
+            CoverageFormat::Llvm => return Vec::from(["-fprofile-instr-generate".into()]),
+        }
+    }
+}
+
+fn percent(hit: usize, total: usize) -> Option<f64> {
+    if total == 0 {
+        return None;
+    }
+    return Some(hit as f64 * 100.0 / total as f64);
+}
+
+fn show_percent(percent: Option<f64>) -> String {
+    match percent {
+        Some(percent) => return format!("{:.1}%", percent),
+        None => return "-".into(),
+    }
+}
+
+// Execution counts for one source file.
+#[derive(Debug, Clone, Default)]
+pub struct FileCoverage {
+    path: PathBuf,
+    lines: BTreeMap<u32, u64>,
+    // Keyed by line, block and branch, like lcov's `BRDA` records.
+    branches: BTreeMap<(u32, u32, u32), u64>,
+}
+
+impl FileCoverage {
+    fn new(path: impl Into<PathBuf>) -> Self {
+        return Self {
+            path: path.into(),
+            ..Default::default()
+        };
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn lines_total(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|count| **count > 0).count()
+    }
+
+    pub fn branches_total(&self) -> usize {
+        self.branches.len()
+    }
+
+    pub fn branches_hit(&self) -> usize {
+        self.branches.values().filter(|count| **count > 0).count()
+    }
+
+    // `None` for files without any lines to cover.
+    pub fn line_percent(&self) -> Option<f64> {
+        return percent(self.lines_hit(), self.lines_total());
+    }
+
+    // `None` for files without branches.
+    pub fn branch_percent(&self) -> Option<f64> {
+        return percent(self.branches_hit(), self.branches_total());
+    }
+
+    // Headers show up once per object that includes them.
+    fn merge(&mut self, other: FileCoverage) {
+        for (line, count) in other.lines {
+            *self.lines.entry(line).or_default() += count;
+        }
+        for (branch, count) in other.branches {
+            *self.branches.entry(branch).or_default() += count;
+        }
+    }
+
+    fn lcov(&self) -> String {
+        let mut out = format!("SF:{}\n", self.path.display());
+        for ((line, block, branch), count) in &self.branches {
+            out.push_str(&format!("BRDA:{},{},{},{}\n", line, block, branch, count));
+        }
+        out.push_str(&format!("BRF:{}\n", self.branches_total()));
+        out.push_str(&format!("BRH:{}\n", self.branches_hit()));
+        for (line, count) in &self.lines {
+            out.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        out.push_str(&format!("LF:{}\n", self.lines_total()));
+        out.push_str(&format!("LH:{}\n", self.lines_hit()));
+        out.push_str("end_of_record\n");
+        out
+    }
+}
+
+// Reads the `SF`, `DA` and `BRDA` records of an lcov tracefile; the totals are recomputed.
+fn parse_lcov(text: &str) -> Vec<FileCoverage> {
+    let mut files = Vec::new();
+    let mut current: Option<FileCoverage> = None;
+    for line in text.lines() {
+        let (tag, value) = line.split_once(':').unwrap_or((line, ""));
+        let fields = value.split(',').collect::<Vec<_>>();
+        match (tag, current.as_mut(), fields.as_slice()) {
+            ("SF", _, _) => current = Some(FileCoverage::new(value)),
+            ("DA", Some(file), [line, count, ..]) => {
+                if let (Ok(line), Ok(count)) = (line.parse(), count.parse::<u64>()) {
+                    *file.lines.entry(line).or_default() += count;
+                }
+            }
+            // `-` marks branches whose line never ran.
+            ("BRDA", Some(file), [line, block, branch, taken]) => {
+                if let (Ok(line), Ok(block), Ok(branch)) =
+                    (line.parse(), block.parse(), branch.parse())
+                {
+                    let taken = taken.parse::<u64>().unwrap_or(0);
+                    *file.branches.entry((line, block, branch)).or_default() += taken;
+                }
+            }
+            ("end_of_record", Some(_), _) => files.extend(current.take()),
+            _ => {}
+        }
+    }
+    files
+}
+
+// Reads the JSON intermediate format of `gcov --json-format`.
+fn parse_gcov(report: &Json) -> Vec<FileCoverage> {
+    let files = report
+        .get("files")
+        .and_then(Json::as_array)
+        .unwrap_or_default();
+    return files
+        .iter()
+        .filter_map(|file| {
+            let mut coverage = FileCoverage::new(file.get("file")?.as_str()?);
+            for line in file.get("lines")?.as_array()? {
+                let number = line.get("line_number")?.as_number()? as u32;
+                let count = line.get("count")?.as_number()? as u64;
+                *coverage.lines.entry(number).or_default() += count;
+                let branches = line
+                    .get("branches")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default();
+                for (i, branch) in branches.iter().enumerate() {
+                    let taken = branch.get("count")?.as_number()? as u64;
+                    *coverage.branches.entry((number, 0, i as u32)).or_default() += taken;
+                }
+            }
+            Some(coverage)
+        })
+        .collect();
+}
+
+fn stdout_of(invocation: &Invocation) -> Option<String> {
+    event!(Level::DEBUG, "executing: {}", invocation);
+    match invocation.to_command().output() {
+        Ok(out) if out.status.success() => {
+            return Some(String::from_utf8_lossy(&out.stdout).into_owned())
+        }
+        Ok(out) => {
+            event!(
+                Level::WARN,
+                "`{}` failed: {}",
+                invocation.get_program(),
+                String::from_utf8_lossy(&out.stderr)
+            );
+            return None;
+        }
+        Err(e) => {
+            event!(Level::WARN, "error occured: `{}`", e);
+            return None;
+        }
+    }
+}
+
+// Removes the counters left behind by earlier runs, which would otherwise add up.
+fn remove_counters(dir: &Path, ext: &str) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            remove_counters(&path, ext);
+        } else if path.extension().is_some_and(|e| e == ext) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                event!(
+                    Level::WARN,
+                    "failed to remove `{}`: `{}`",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+// Line and branch coverage gathered by running instrumented binaries, written to
+// `out_dir/coverage` as an lcov `coverage.info` and a plain-text `summary.txt`.
+pub struct CoverageReport {
+    files: Vec<FileCoverage>,
+}
+
+impl CoverageReport {
+    // Runs each of `runs` (the binary or the test suite, built from `command` with
+    // coverage enabled) on fresh counters and gathers what they executed. Files
+    // outside the working directory, like system headers, are left out.
+    pub fn collect(command: &CompileCommand, runs: &[Invocation]) -> Option<Self> {
+        let Some(format) = command.coverage_format() else {
+            println!("[ERROR]: coverage is not enabled for this build");
+            return None;
+        };
+        let dir = command.resolve(&command.out_dir().join("coverage"));
+        let (dir, root) = match std::fs::create_dir_all(&dir)
+            .and_then(|_| Ok((dir.canonicalize()?, command.working_dir().canonicalize()?)))
+        {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                event!(Level::WARN, "failed to create coverage dir: `{}`", e);
+                return None;
+            }
+        };
+        match format {
+            CoverageFormat::Gcov => {
+                remove_counters(&command.resolve(&command.out_dir().join("obj")), "gcda")
+            }
+            CoverageFormat::Llvm => remove_counters(&dir, "profraw"),
+        }
+
+        for (i, run) in runs.iter().enumerate() {
+            let mut run = run.clone();
+            if format == CoverageFormat::Llvm {
+                let profile = dir.join(format!("run{}-%p.profraw", i));
+                run = run.env("LLVM_PROFILE_FILE", profile.display());
+            }
+            println!("[Running]: {}", run.get_program());
+            event!(Level::DEBUG, "executing: {}", run);
+            match run.to_command().status() {
+                // Failing tests still say what they covered.
+                Ok(status) if !status.success() => {
+                    event!(
+                        Level::WARN,
+                        "`{}` exited with {}",
+                        run.get_program(),
+                        status
+                    )
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    event!(Level::WARN, "error occured: `{}`", e);
+                    return None;
+                }
+            }
+        }
+
+        let gathered = match format {
+            CoverageFormat::Gcov => Self::gcov(command),
+            CoverageFormat::Llvm => Self::llvm(command, runs, &dir)?,
+        };
+        let mut files: Vec<FileCoverage> = Vec::new();
+        for mut file in gathered {
+            if file.path.is_absolute() {
+                match file.path.strip_prefix(&root) {
+                    Ok(path) => file.path = path.to_path_buf(),
+                    Err(_) => continue,
+                }
+            }
+            file.path = file.path.normalize();
+            match files.iter_mut().find(|known| known.path == file.path) {
+                Some(known) => known.merge(file),
+                None => files.push(file),
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let report = Self { files };
+        let info = dir.join("coverage.info");
+        let written = std::fs::write(&info, report.lcov())
+            .and_then(|_| std::fs::write(dir.join("summary.txt"), report.to_string()));
+        if let Err(e) = written {
+            event!(Level::WARN, "failed to write coverage report: `{}`", e);
+        }
+        println!(
+            "[Coverage]: {} lines, {} branches ({})",
+            show_percent(report.line_percent()),
+            show_percent(report.branch_percent()),
+            info.display()
+        );
+        return Some(report);
+    }
+
+    // One `gcov` call per object; units that never ran still report their lines.
+    fn gcov(command: &CompileCommand) -> Vec<FileCoverage> {
+        let mut files = Vec::new();
+        for unit in command.units() {
+            if !SourceKind::of(unit.src()).is_c() {
+                continue;
+            }
+            let invocation = Invocation::new("gcov")
+                .cwd(command.working_dir())
+                .args(["-b", "--json-format", "--stdout"])
+                .arg(unit.out().display());
+            let Some(out) = stdout_of(&invocation) else {
+                continue;
+            };
+            match Json::parse(&out) {
+                Some(report) => files.extend(parse_gcov(&report)),
+                None => event!(Level::WARN, "unreadable gcov report for `{}`", unit),
+            }
+        }
+        files
+    }
+
+    // The runs' programs carry the coverage mapping `llvm-cov` needs.
+    fn llvm(
+        command: &CompileCommand,
+        runs: &[Invocation],
+        dir: &Path,
+    ) -> Option<Vec<FileCoverage>> {
+        let profiles = std::fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "profraw"))
+            .collect::<Vec<_>>();
+        if profiles.is_empty() {
+            println!("[ERROR]: no coverage data was written");
+            return None;
+        }
+        let profdata = dir.join("coverage.profdata");
+        let merge = Invocation::new("llvm-profdata")
+            .args(["merge", "-sparse"])
+            .args(profiles.iter().map(|profile| profile.display()))
+            .arg("-o")
+            .arg(profdata.display());
+        stdout_of(&merge)?;
+        let mut export = Invocation::new("llvm-cov")
+            .cwd(command.working_dir())
+            .args(["export", "-format=lcov"])
+            .arg(format!("-instr-profile={}", profdata.display()));
+        for (i, run) in runs.iter().enumerate() {
+            let binary = run.get_cwd().join(run.get_program());
+            if i > 0 {
+                export = export.arg("-object");
+            }
+            export = export.arg(binary.display());
+        }
+        return Some(parse_lcov(&stdout_of(&export)?));
+    }
+
+    pub fn files(&self) -> &[FileCoverage] {
+        &self.files
+    }
+
+    pub fn line_percent(&self) -> Option<f64> {
+        let hit = self.files.iter().map(FileCoverage::lines_hit).sum();
+        let total = self.files.iter().map(FileCoverage::lines_total).sum();
+        return percent(hit, total);
+    }
+
+    pub fn branch_percent(&self) -> Option<f64> {
+        let hit = self.files.iter().map(FileCoverage::branches_hit).sum();
+        let total = self.files.iter().map(FileCoverage::branches_total).sum();
+        return percent(hit, total);
+    }
+
+    // The report as an lcov tracefile, for `genhtml` or CI services.
+    pub fn lcov(&self) -> String {
+        let mut out = String::from("TN:\n");
+        for file in &self.files {
+            out.push_str(&file.lcov());
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .files
+            .iter()
+            .map(|file| file.path.display().to_string().len())
+            .chain([5])
+            .max()
+            .unwrap_or_default();
+        writeln!(f, "{:<width$}  {:>7}  {:>8}", "File", "Lines", "Branches")?;
+        for file in &self.files {
+            writeln!(
+                f,
+                "{:<width$}  {:>7}  {:>8}",
+                file.path.display(),
+                show_percent(file.line_percent()),
+                show_percent(file.branch_percent())
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<width$}  {:>7}  {:>8}",
+            "Total",
+            show_percent(self.line_percent()),
+            show_percent(self.branch_percent())
+        )
+    }
+}
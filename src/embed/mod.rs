@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+
+// How a resource's bytes get into the object file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmbedMode {
+    // A byte array literal. Works everywhere, but large files make slow sources.
+    #[default]
+    Array,
+    // C23 `#embed`; needs GCC 15 or Clang 19.
+    Embed,
+    // The assembler's `.incbin`; GCC-compatible compilers only.
+    Incbin,
+}
+
+// A file exposed to C as `const unsigned char <symbol>[]` and `const size_t <symbol>_len`.
+// The array carries a trailing NUL that isn't counted, so text resources can be used as strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    path: PathBuf,
+    symbol: String,
+}
+
+// Turns a file name into a C identifier: `shaders/blit.frag` -> `blit_frag`.
+pub(crate) fn symbol_for(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut symbol = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect::<String>();
+    if !symbol.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        symbol.insert(0, '_');
+    }
+    symbol
+}
+
+fn c_str(path: &Path) -> String {
+    let path = path.display().to_string().replace('\\', "/");
+    return format!("\"{}\"", path.replace('"', "\\\""));
+}
+
+impl Resource {
+    pub fn new(path: impl Into<PathBuf>, symbol: impl ToString) -> Self {
+        return Self {
+            path: path.into(),
+            symbol: symbol.to_string(),
+        };
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn header_name(&self) -> String {
+        return format!("{}.h", self.symbol);
+    }
+
+    pub fn header(&self) -> String {
+        let guard = format!("CBUILD_EMBED_{}_H", self.symbol.to_ascii_uppercase());
+        let mut out = format!(
+            "/* Generated by cbuild from `{}`; do not edit. */\n",
+            self.path.display()
+        );
+        out.push_str(&format!("#ifndef {}\n#define {}\n\n", guard, guard));
+        out.push_str("#include <stddef.h>\n\n");
+        out.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+        out.push_str(&format!("extern const unsigned char {}[];\n", self.symbol));
+        out.push_str(&format!("extern const size_t {}_len;\n", self.symbol));
+        out.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n");
+        out.push_str(&format!("#endif /* {} */\n", guard));
+        out
+    }
+
+    // `resolved` is the resource as seen from the compiler: `#embed` and `.incbin`
+    // search relative to the including file, so it has to be absolute.
+    pub fn source(&self, mode: EmbedMode, resolved: &Path) -> std::io::Result<String> {
+        let symbol = &self.symbol;
+        let mut out = format!(
+            "/* Generated by cbuild from `{}`; do not edit. */\n#include \"{}\"\n\n",
+            self.path.display(),
+            self.header_name()
+        );
+        match mode {
+            EmbedMode::Array => {
+                let bytes = std::fs::read(resolved)?;
+                out.push_str(&format!("const unsigned char {}[] = {{\n", symbol));
+                for line in bytes.chunks(12) {
+                    let line = line
+                        .iter()
+                        .map(|byte| format!("0x{:02x},", byte))
+                        .collect::<Vec<_>>();
+                    out.push_str(&format!("    {}\n", line.join(" ")));
+                }
+                out.push_str("    0x00\n};\n");
+                out.push_str(&format!("const size_t {}_len = {};\n", symbol, bytes.len()));
+            }
+            EmbedMode::Embed => {
+                out.push_str(&format!("const unsigned char {}[] = {{\n", symbol));
+                out.push_str(&format!("#embed {} suffix(,)\n", c_str(resolved)));
+                out.push_str("    0x00\n};\n");
+                out.push_str(&format!(
+                    "const size_t {}_len = sizeof({}) - 1;\n",
+                    symbol, symbol
+                ));
+            }
+            EmbedMode::Incbin => {
+                let len = std::fs::metadata(resolved)?.len();
+                // Mach-O prefixes C symbols with an underscore and has no `.rodata`.
+                out.push_str("#ifdef __APPLE__\n");
+                out.push_str("#define CBUILD_SECTION \".const_data\\n\"\n");
+                out.push_str("#define CBUILD_PREFIX \"_\"\n");
+                out.push_str("#define CBUILD_RESTORE \".text\\n\"\n");
+                out.push_str("#else\n");
+                out.push_str("#define CBUILD_SECTION \".section .rodata\\n\"\n");
+                out.push_str("#define CBUILD_PREFIX \"\"\n");
+                out.push_str("#define CBUILD_RESTORE \".previous\\n\"\n");
+                out.push_str("#endif\n\n");
+                out.push_str("__asm__(\n    CBUILD_SECTION\n");
+                out.push_str(&format!(
+                    "    \".global \" CBUILD_PREFIX \"{}\\n\"\n",
+                    symbol
+                ));
+                out.push_str("    \".balign 16\\n\"\n");
+                out.push_str(&format!("    CBUILD_PREFIX \"{}:\\n\"\n", symbol));
+                out.push_str(&format!(
+                    "    \".incbin \\\"\" {} \"\\\"\\n\"\n",
+                    c_str(resolved)
+                ));
+                out.push_str("    \".byte 0\\n\"\n");
+                out.push_str("    CBUILD_RESTORE\n);\n");
+                out.push_str(&format!("const size_t {}_len = {};\n", symbol, len));
+            }
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,186 @@
+use crate::linker::{BinType, LinkerCommand};
+use crate::toolchain::import_lib;
+use std::path::{Path, PathBuf};
+
+// Escapes a literal for use inside a quoted CMake argument.
+fn escape(value: &str) -> String {
+    return value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace(';', "\\;");
+}
+
+fn cmake_str(value: &str) -> String {
+    return format!("\"{}\"", escape(value));
+}
+
+// Emits `<Name>Config.cmake` and `<Name>ConfigVersion.cmake` describing the
+// artifact of a `LinkerCommand` as the imported target `<Name>::<Name>`.
+pub struct CMakePackageGenerator<'a, 'b> {
+    linker_command: &'a LinkerCommand<'b>,
+    name: String,
+    version: String,
+    install_tree: bool,
+    include_dir: Option<PathBuf>,
+}
+
+impl<'a, 'b> CMakePackageGenerator<'a, 'b> {
+    pub fn new(
+        linker_command: &'a LinkerCommand<'b>,
+        name: impl ToString,
+        version: impl ToString,
+    ) -> Self {
+        return Self {
+            linker_command,
+            name: name.to_string(),
+            version: version.to_string(),
+            install_tree: false,
+            include_dir: Some(PathBuf::from("include")),
+        };
+    }
+
+    // Describes an install tree laid out as `<prefix>/lib/<artifact>` (DLLs in
+    // `<prefix>/bin`), `<prefix>/include` and `<prefix>/lib/cmake/<Name>/`, with paths
+    // relative to the config file. Otherwise the package points at the build tree with
+    // absolute paths.
+    pub fn install_tree(mut self, install_tree: bool) -> Self {
+        self.install_tree = install_tree;
+        return self;
+    }
+
+    // The public header directory consumers get on their include path, relative to the
+    // working directory; `include` by default. The project's other include directories
+    // and definitions stay private. Ignored for an install tree, which uses
+    // `<prefix>/include`.
+    pub fn include_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.include_dir = dir;
+        return self;
+    }
+
+    // Escaped absolute path.
+    fn absolute(&self, path: &Path) -> String {
+        let path = self.linker_command.compile_command().resolve(path);
+        let path = std::path::absolute(&path).unwrap_or(path);
+        return escape(&path.display().to_string().replace('\\', "/"));
+    }
+
+    fn location(&self, artifact: &Path) -> String {
+        if self.install_tree {
+            let name = artifact.file_name().unwrap_or_default().to_string_lossy();
+            let dir = if artifact.extension().is_some_and(|ext| ext == "dll") {
+                "bin"
+            } else {
+                "lib"
+            };
+            return format!("${{_IMPORT_PREFIX}}/{}/{}", dir, escape(&name));
+        }
+        return self.absolute(artifact);
+    }
+
+    fn property(content: &mut String, name: &str, values: &[String]) {
+        if !values.is_empty() {
+            content.push_str(&format!("    {} \"{}\"\n", name, values.join(";")));
+        }
+    }
+
+    pub fn config(&self) -> String {
+        let command = self.linker_command.compile_command();
+        let target = format!("{0}::{0}", self.name);
+        let artifact = self.linker_command.artifact_path();
+        let kind = match self.linker_command.bin_type() {
+            BinType::StaticLib => "STATIC",
+            BinType::DynamicLib => "SHARED",
+            BinType::Binary => "UNKNOWN",
+        };
+        let includes = if self.install_tree {
+            Vec::from(["${_IMPORT_PREFIX}/include".to_string()])
+        } else {
+            // CMake rejects imported targets with missing include directories.
+            self.include_dir
+                .iter()
+                .filter(|dir| command.resolve(dir).is_dir())
+                .map(|dir| self.absolute(dir))
+                .collect()
+        };
+        let links = self
+            .linker_command
+            .links()
+            .iter()
+            .map(|link| escape(&link.display().to_string()))
+            .collect::<Vec<_>>();
+        let link_dirs = self
+            .linker_command
+            .link_dirs()
+            .iter()
+            .map(|dir| self.absolute(dir))
+            .collect::<Vec<_>>();
+
+        let mut content = String::from("# Generated by cbuild; do not edit.\n");
+        if self.install_tree {
+            content.push_str(
+                "get_filename_component(_IMPORT_PREFIX \"${CMAKE_CURRENT_LIST_DIR}/../../..\" ABSOLUTE)\n",
+            );
+        }
+        content.push_str(&format!("\nif(NOT TARGET {})\n", target));
+        if kind == "UNKNOWN" {
+            content.push_str(&format!("  add_executable({} IMPORTED)\n", target));
+        } else {
+            content.push_str(&format!("  add_library({} {} IMPORTED)\n", target, kind));
+        }
+        content.push_str(&format!("  set_target_properties({} PROPERTIES\n", target));
+        Self::property(
+            &mut content,
+            "IMPORTED_LOCATION",
+            &[self.location(&artifact)],
+        );
+        if kind == "SHARED" && artifact.extension().is_some_and(|ext| ext == "dll") {
+            let implib = self.location(&import_lib(&artifact));
+            Self::property(&mut content, "IMPORTED_IMPLIB", &[implib]);
+        }
+        if kind != "UNKNOWN" {
+            Self::property(&mut content, "INTERFACE_INCLUDE_DIRECTORIES", &includes);
+            Self::property(&mut content, "INTERFACE_LINK_LIBRARIES", &links);
+            Self::property(&mut content, "INTERFACE_LINK_DIRECTORIES", &link_dirs);
+        }
+        content.push_str("  )\nendif()\n");
+        if self.install_tree {
+            content.push_str("\nunset(_IMPORT_PREFIX)\n");
+        }
+        content
+    }
+
+    // Any version with the same major number and at least the requested version is compatible.
+    pub fn config_version(&self) -> String {
+        let major = self.version.split('.').next().unwrap_or("0");
+        return format!(
+            "# Generated by cbuild; do not edit.
+set(PACKAGE_VERSION {version})
+
+if(PACKAGE_FIND_VERSION VERSION_GREATER PACKAGE_VERSION)
+  set(PACKAGE_VERSION_COMPATIBLE FALSE)
+elseif(PACKAGE_FIND_VERSION_MAJOR STREQUAL {major})
+  set(PACKAGE_VERSION_COMPATIBLE TRUE)
+  if(PACKAGE_FIND_VERSION VERSION_EQUAL PACKAGE_VERSION)
+    set(PACKAGE_VERSION_EXACT TRUE)
+  endif()
+else()
+  set(PACKAGE_VERSION_COMPATIBLE FALSE)
+endif()
+",
+            version = cmake_str(&self.version),
+            major = cmake_str(major)
+        );
+    }
+
+    // Writes both files into `dir`, e.g. `<prefix>/lib/cmake/<Name>`.
+    pub fn write(&self, dir: impl AsRef<Path>) -> std::io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let config = dir.join(format!("{}Config.cmake", self.name));
+        let version = dir.join(format!("{}ConfigVersion.cmake", self.name));
+        std::fs::write(&config, self.config())?;
+        std::fs::write(&version, self.config_version())?;
+        return Ok(Vec::from([config, version]));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::compiler::{CompilationFile, SourceKind};
+use crate::invocation::{shell_quote, Invocation};
+use crate::linker::{BinType, LinkerCommand};
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+const IN: &str = "@CBUILD_IN@";
+const OUT: &str = "@CBUILD_OUT@";
+
+// Escapes a path for use in target and prerequisite lists.
+fn escape_path(path: &Path) -> String {
+    return path
+        .display()
+        .to_string()
+        .replace('$', "$$")
+        .replace('#', "\\#")
+        .replace(' ', "\\ ")
+        .replace(':', "\\:");
+}
+
+fn escape_paths<'p>(paths: impl IntoIterator<Item = &'p PathBuf>) -> String {
+    return paths
+        .into_iter()
+        .map(|path| escape_path(path))
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+// `include` takes file names, so a colon there is not a rule separator.
+fn include_paths<'p>(paths: impl IntoIterator<Item = &'p PathBuf>) -> String {
+    paths
+        .into_iter()
+        .map(|path| escape_path(path).replace("\\:", ":"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Quotes paths for the shell in a recipe line, where make's own escapes don't apply.
+fn shell_paths<'p>(paths: impl IntoIterator<Item = &'p PathBuf>) -> String {
+    paths
+        .into_iter()
+        .map(|path| shell_quote(&path.display().to_string()).replace('$', "$$"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Renders a recipe line; the shell sees quoted arguments, make sees `$$` for `$`.
+// Automatic variables are quoted as a whole since make expands them unescaped.
+fn recipe(invocation: &Invocation) -> String {
+    return invocation
+        .to_string()
+        .replace('$', "$$")
+        .replace(IN, "'$<'")
+        .replace(OUT, "'$@'");
+}
+
+// Emits a self-contained Makefile for a `LinkerCommand` and the `CompileCommand`
+// it links. Like the commands themselves, paths are relative to the working directory.
+// Embedded resources are written by the generator, so they only follow changes to
+// the resource files through `regenerate`.
+pub struct MakefileGenerator<'a, 'b> {
+    linker_command: &'a LinkerCommand<'b>,
+    regenerate: Option<(String, Vec<PathBuf>)>,
+}
+
+impl<'a, 'b> MakefileGenerator<'a, 'b> {
+    pub fn new(linker_command: &'a LinkerCommand<'b>) -> Self {
+        return Self {
+            linker_command,
+            regenerate: None,
+        };
+    }
+
+    // Reruns `command` to rewrite `Makefile` whenever one of `inputs` or an embedded
+    // resource changes; make then restarts with the new file.
+    pub fn regenerate(
+        mut self,
+        command: impl IntoIterator<Item = impl ToString>,
+        inputs: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> Self {
+        let command = command
+            .into_iter()
+            .map(|arg| shell_quote(&arg.to_string()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let inputs = inputs.into_iter().map(|input| input.into()).collect();
+        self.regenerate = Some((command.replace('$', "$$"), inputs));
+        return self;
+    }
+
+    // Renders the Makefile. This also writes the embedded resource and unity sources
+    // under `out_dir`, since no rule produces them.
+    pub fn generate(&self) -> String {
+        let command = self.linker_command.compile_command();
+        command.write_generated_sources();
+        let link = self.linker_command.build_command();
+        let artifacts = link.get_outputs();
+        let objects = command
+            .units()
+            .iter()
+            .map(|unit| unit.out().to_path_buf())
+            .collect::<Vec<_>>();
+
+        let mut content = String::from("# Generated by cbuild; do not edit.\n");
+        // Steps with several outputs run once through a grouped target (`&:`).
+        if command.steps().iter().any(|step| step.outputs().len() > 1) {
+            content.push_str("# Requires GNU make 4.3 or newer.\n");
+        }
+        content.push_str("PREFIX ?= /usr/local\n");
+        content.push_str("DESTDIR ?=\n\n");
+        content.push_str(".PHONY: all clean install\n\n");
+        content.push_str(&format!("all: {}\n\n", escape_paths(artifacts)));
+
+        let resources = command
+            .resources()
+            .iter()
+            .map(|resource| resource.path().to_path_buf())
+            .collect::<Vec<_>>();
+        if let Some((regenerate, inputs)) = &self.regenerate {
+            content.push_str(&format!(
+                "Makefile: {}\n\t{}\n\n",
+                escape_paths(inputs.iter().chain(&resources)),
+                regenerate
+            ));
+        } else if !resources.is_empty() {
+            event!(
+                Level::WARN,
+                "embedded resources are only refreshed by rerunning the generator; see `regenerate`"
+            );
+        }
+
+        content.push_str(&format!(
+            "{}: {}\n\t@mkdir -p \"$$(dirname '$@')\"\n\t{}\n\n",
+            escape_paths(artifacts),
+            escape_paths(link.get_inputs()),
+            recipe(&link)
+        ));
+
+        let mut prerequisites = Vec::new();
+        let mut generated = Vec::new();
+        // Compiles wait for every step, since any of them may write a header.
+        let mut order_only = Vec::new();
+        for (i, step) in command.steps().iter().enumerate() {
+            let invocation = step.invocation();
+            let mut line = recipe(invocation);
+            if invocation.get_cwd() != Path::new(".") {
+                line = format!(
+                    "cd {} && {}",
+                    shell_quote(&invocation.get_cwd().display().to_string()).replace('$', "$$"),
+                    line
+                );
+            }
+            let (targets, separator) = match step.outputs() {
+                [] => {
+                    let phony = PathBuf::from(format!("step_{}", i));
+                    content.push_str(&format!(".PHONY: {}\n", escape_path(&phony)));
+                    (Vec::from([phony]), ":")
+                }
+                [_] => (step.outputs().to_vec(), ":"),
+                _ => (step.outputs().to_vec(), "&:"),
+            };
+            content.push_str(&format!(
+                "{}{} {}\n\t@mkdir -p \"$$(dirname '$@')\"\n\t{}\n\n",
+                escape_paths(&targets),
+                separator,
+                escape_paths(step.inputs()),
+                line
+            ));
+            generated.extend(step.outputs().iter().cloned());
+            order_only.extend(targets);
+        }
+
+        if let Some(pch) = command.pch_invocation() {
+            content.push_str(&format!(
+                "{}: {}\n\t@mkdir -p \"$$(dirname '$@')\"\n\t{}\n\n",
+                escape_paths(pch.get_outputs()),
+                escape_paths(pch.get_inputs()),
+                recipe(&pch)
+            ));
+            prerequisites.extend(pch.get_outputs().iter().take(1).cloned());
+            generated.extend(pch.get_outputs().iter().cloned());
+        }
+
+        let template = CompilationFile::from_parts(IN, OUT);
+        let mut depfiles = Vec::new();
+        for unit in command.units() {
+            let kind = SourceKind::of(unit.src());
+            let compile = command.build_command_for_kind(&template, kind);
+            if let Some((_, "gcc")) = command.depfile_args(kind, Path::new(&format!("{}.d", OUT))) {
+                depfiles.push(PathBuf::from(format!("{}.d", unit.out().display())));
+            }
+            let mut inputs = Vec::from([unit.src().to_path_buf()]);
+            if kind.is_c() {
+                inputs.extend(prerequisites.iter().cloned());
+            }
+            inputs.extend(unit.deps().iter().cloned());
+            let mut prerequisites = escape_paths(&inputs);
+            if !order_only.is_empty() {
+                prerequisites.push_str(" | ");
+                prerequisites.push_str(&escape_paths(&order_only));
+            }
+            content.push_str(&format!(
+                "{}: {}\n\t@mkdir -p \"$$(dirname '$@')\"\n\t{}\n\n",
+                escape_path(unit.out()),
+                prerequisites,
+                recipe(&compile)
+            ));
+        }
+
+        if !depfiles.is_empty() {
+            content.push_str(&format!("-include {}\n\n", include_paths(&depfiles)));
+        }
+
+        // MSVC writes a DLL's import library alongside it.
+        let import_libs = link
+            .get_args()
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("/IMPLIB:"))
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        generated.extend(objects);
+        generated.extend(artifacts.iter().cloned());
+        generated.extend(import_libs.iter().cloned());
+        generated.extend(depfiles);
+        content.push_str(&format!("clean:\n\trm -f {}\n\n", shell_paths(&generated)));
+
+        // DLLs are found next to the executables that load them, so they go to `bin`.
+        let is_dll = artifacts
+            .iter()
+            .any(|artifact| artifact.extension().is_some_and(|ext| ext == "dll"));
+        let (dir, mode) = match self.linker_command.bin_type() {
+            BinType::Binary => ("bin", "755"),
+            BinType::DynamicLib if is_dll => ("bin", "755"),
+            BinType::DynamicLib => ("lib", "755"),
+            BinType::StaticLib => ("lib", "644"),
+        };
+        let mut installs = Vec::from([(dir, mode, artifacts.to_vec())]);
+        if !import_libs.is_empty() {
+            installs.push(("lib", "644", import_libs));
+        }
+        content.push_str("install: all\n");
+        for (dir, mode, files) in installs {
+            content.push_str(&format!(
+                "\tinstall -d \"$(DESTDIR)$(PREFIX)/{dir}\"\n\tinstall -m {mode} {} \"$(DESTDIR)$(PREFIX)/{dir}/\"\n",
+                shell_paths(&files)
+            ));
+        }
+        return content;
+    }
+}
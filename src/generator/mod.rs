@@ -1,9 +1,36 @@
 use crate::compiler::{CompilationFile, CompileCommand};
+use crate::toolchain::Builtins;
 use std::path::PathBuf;
 
+mod cmake;
+mod make;
+mod ninja;
+
+pub use cmake::CMakePackageGenerator;
+pub use make::MakefileGenerator;
+pub use ninja::NinjaGenerator;
+
 pub struct ConfigGenerator<'a> {
     compiler_command: &'a mut CompileCommand,
     root_dir: PathBuf,
+    builtins: Option<Builtins>,
+}
+
+pub(crate) fn json_str(value: &str) -> String {
+    let mut out = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
 impl<'a> ConfigGenerator<'a> {
@@ -11,38 +38,53 @@ impl<'a> ConfigGenerator<'a> {
         return Self {
             compiler_command,
             root_dir,
+            builtins: None,
         };
     }
 
-    fn generate_file(&self, file: &CompilationFile, command: &mut CompileCommand) -> String {
+    // Spells out the compiler's implicit include paths and macros in every entry.
+    pub fn embed_builtins(mut self, builtins: Builtins) -> Self {
+        self.builtins = Some(builtins);
+        return self;
+    }
+
+    fn generate_file(&self, file: &CompilationFile) -> String {
+        let invocation = self.compiler_command.raw_command_for_file(file);
+        let mut arguments = std::iter::once(invocation.get_program())
+            .chain(invocation.get_args().iter().map(|arg| arg.as_str()))
+            .map(json_str)
+            .collect::<Vec<_>>();
+        if let Some(builtins) = &self.builtins {
+            arguments.extend(builtins.args().iter().map(|arg| json_str(arg)));
+        }
+
         let mut entry = String::new();
-        entry.push_str("{");
-        entry.push_str(&format!("\"directory\": {:?},", self.root_dir));
-        entry.push_str(&format!("\"file\": {:?},", file.src()));
-        entry.push_str(&format!("\"output\": {:?},", file.out()));
-        entry.push_str(&format!("\"arguments\": [\"{}\", ", command.compiler()));
+        entry.push('{');
         entry.push_str(&format!(
-            "\"-c\", {:?}, \"-o\", {:?}",
-            file.src(),
-            file.out()
+            "\"directory\": {},",
+            json_str(&self.root_dir.display().to_string())
         ));
-        entry.push_str(&command.args());
-        entry.push_str("]");
-
-        entry.push_str("}");
+        entry.push_str(&format!(
+            "\"file\": {},",
+            json_str(&file.src().display().to_string())
+        ));
+        entry.push_str(&format!(
+            "\"output\": {},",
+            json_str(&file.out().display().to_string())
+        ));
+        entry.push_str(&format!("\"arguments\": [{}]", arguments.join(", ")));
+        entry.push('}');
         entry
     }
 
     pub fn generate(&mut self) -> String {
         let mut content = String::new();
         content.push_str("[\n");
-        let mut command = self.compiler_command.clone();
-        for (i, file) in self.compiler_command.files().clone().iter().enumerate() {
+        for (i, file) in self.compiler_command.files().iter().enumerate() {
             if i != 0 {
                 content.push_str(", ");
             }
-            let file = self.generate_file(file, &mut command);
-            content.push_str(&file);
+            content.push_str(&self.generate_file(file));
         }
 
         content.push_str("\n]");
@@ -0,0 +1,261 @@
+use crate::compiler::{CompilationFile, CompileCommand, SourceKind};
+use crate::invocation::{shell_quote, Invocation};
+use crate::linker::LinkerCommand;
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+const IN: &str = "@CBUILD_IN@";
+const OUT: &str = "@CBUILD_OUT@";
+
+// Escapes a path for use in a `build` line.
+fn escape_path(path: &Path) -> String {
+    return path
+        .display()
+        .to_string()
+        .replace('$', "$$")
+        .replace(' ', "$ ")
+        .replace(':', "$:");
+}
+
+fn escape_paths<'p>(paths: impl IntoIterator<Item = &'p PathBuf>) -> String {
+    return paths
+        .into_iter()
+        .map(|path| escape_path(path))
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+// Renders a shell command line, keeping the `$in`/`$out` placeholders live for Ninja.
+fn command_line(invocation: &Invocation) -> String {
+    return invocation
+        .to_string()
+        .replace('$', "$$")
+        .replace(IN, "$in")
+        .replace(OUT, "$out");
+}
+
+// Emits a `build.ninja` that runs the same command lines as `CompileCommand::run`
+// and `LinkerCommand::run`. Paths are relative to the commands' working directory,
+// so Ninja has to be started from there. `build.ninja` only rebuilds itself when
+// `regenerate` says how it was written; without it, rerun the generator by hand.
+// That includes changes to embedded resources, whose sources the generator writes.
+pub struct NinjaGenerator {
+    rules: Vec<String>,
+    builds: Vec<String>,
+    defaults: Vec<PathBuf>,
+    regenerate: Option<(String, Vec<PathBuf>)>,
+    resources: Vec<PathBuf>,
+}
+
+impl Default for NinjaGenerator {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl NinjaGenerator {
+    pub fn new() -> Self {
+        return Self {
+            rules: Vec::new(),
+            builds: Vec::new(),
+            defaults: Vec::new(),
+            regenerate: None,
+            resources: Vec::new(),
+        };
+    }
+
+    fn rule(&mut self, kind: &str, command: String, extra: &[String]) -> String {
+        let name = format!("{}_{}", kind, self.rules.len());
+        let mut rule = format!("rule {}\n  command = {}\n", name, command);
+        if !extra.iter().any(|line| line.starts_with("description =")) {
+            rule.push_str(&format!("  description = {} $out\n", kind.to_uppercase()));
+        }
+        for line in extra {
+            rule.push_str(&format!("  {}\n", line));
+        }
+        self.rules.push(rule);
+        name
+    }
+
+    // One rule per kind of source, with the tool's depfile hooked up where it has one.
+    fn compile_rule(&mut self, command: &CompileCommand, kind: SourceKind) -> String {
+        let template = CompilationFile::from_parts(IN, OUT);
+        let mut invocation = command.build_command_for_kind(&template, kind);
+        let mut extra = Vec::new();
+        let dep = PathBuf::from(format!("{}.d", OUT));
+        match command.depfile_args(kind, &dep) {
+            // The compile command already writes `$out.d`.
+            Some((_, "gcc")) => {
+                extra.push("deps = gcc".into());
+                extra.push("depfile = $out.d".into());
+            }
+            // Only Ninja reads the includes MSVC prints, so `run` leaves them out.
+            Some((args, format)) => {
+                invocation = invocation.args(args);
+                extra.push(format!("deps = {}", format));
+            }
+            None => {}
+        }
+        let name = match kind {
+            SourceKind::C => "cc",
+            SourceKind::Assembly | SourceKind::PreprocessedAssembly => "as",
+            SourceKind::Nasm => "nasm",
+        };
+        return self.rule(name, command_line(&invocation), &extra);
+    }
+
+    // Adds the builds for `command`. This writes the embedded resource and unity
+    // sources under its `out_dir` right away, since no build line produces them.
+    pub fn compile(mut self, command: &CompileCommand) -> Self {
+        command.write_generated_sources();
+        self.resources.extend(
+            command
+                .resources()
+                .iter()
+                .map(|resource| resource.path().to_path_buf()),
+        );
+        // Compiles wait for every step, since any of them may write a header.
+        let mut order_only = Vec::new();
+        for (i, step) in command.steps().iter().enumerate() {
+            let invocation = step.invocation();
+            let mut line = command_line(invocation);
+            if invocation.get_cwd() != Path::new(".") {
+                line = format!(
+                    "cd {} && {}",
+                    shell_quote(&invocation.get_cwd().display().to_string()).replace('$', "$$"),
+                    line
+                );
+            }
+            let rule = self.rule(
+                "step",
+                line,
+                &[format!("description = STEP {}", step.name())],
+            );
+            let outputs = match step.outputs().is_empty() {
+                true => Vec::from([PathBuf::from(format!("step_{}", i))]),
+                false => step.outputs().to_vec(),
+            };
+            self.builds.push(format!(
+                "build {}: {} {}\n",
+                escape_paths(&outputs),
+                rule,
+                escape_paths(step.inputs())
+            ));
+            order_only.extend(outputs);
+        }
+
+        let mut implicit = Vec::new();
+        if let Some(pch) = command.pch_invocation() {
+            let pch_rule = self.rule("pch", command_line(&pch), &[]);
+            self.builds.push(format!(
+                "build {}: {} {}\n",
+                escape_paths(pch.get_outputs()),
+                pch_rule,
+                escape_paths(pch.get_inputs())
+            ));
+            implicit.extend(pch.get_outputs().iter().take(1).cloned());
+        }
+
+        let mut rules: Vec<(SourceKind, String)> = Vec::new();
+        for unit in command.units() {
+            let kind = SourceKind::of(unit.src());
+            let rule = match rules.iter().find(|(k, _)| *k == kind) {
+                Some((_, rule)) => rule.clone(),
+                None => {
+                    let rule = self.compile_rule(command, kind);
+                    rules.push((kind, rule.clone()));
+                    rule
+                }
+            };
+            let mut build = format!(
+                "build {}: {} {}",
+                escape_path(unit.out()),
+                rule,
+                escape_path(unit.src())
+            );
+            // The precompiled header only applies to C units.
+            let pch = implicit.iter().filter(|_| kind.is_c());
+            if pch.clone().next().is_some() || !unit.deps().is_empty() {
+                build.push_str(" | ");
+                build.push_str(&escape_paths(pch.chain(unit.deps())));
+            }
+            if !order_only.is_empty() {
+                build.push_str(" || ");
+                build.push_str(&escape_paths(&order_only));
+            }
+            build.push('\n');
+            self.builds.push(build);
+            self.defaults.push(unit.out().to_path_buf());
+        }
+        self
+    }
+
+    pub fn link(mut self, command: &LinkerCommand) -> Self {
+        let compile_outs = command
+            .compile_command()
+            .units()
+            .iter()
+            .map(|unit| unit.out().to_path_buf())
+            .collect::<Vec<_>>();
+        self = self.compile(command.compile_command());
+        self.defaults.retain(|out| !compile_outs.contains(out));
+
+        let invocation = command.build_command();
+        let rule = self.rule("link", command_line(&invocation), &[]);
+        self.builds.push(format!(
+            "build {}: {} {}\n",
+            escape_paths(invocation.get_outputs()),
+            rule,
+            escape_paths(invocation.get_inputs())
+        ));
+        self.defaults
+            .extend(invocation.get_outputs().iter().cloned());
+        self
+    }
+
+    // Reruns `command` to rewrite `build.ninja` whenever one of `inputs` or an embedded
+    // resource changes. Opt-in: only the caller knows which command produced the file.
+    pub fn regenerate(
+        mut self,
+        command: impl IntoIterator<Item = impl ToString>,
+        inputs: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> Self {
+        let command = command
+            .into_iter()
+            .map(|arg| shell_quote(&arg.to_string()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let inputs = inputs.into_iter().map(|input| input.into()).collect();
+        self.regenerate = Some((command.replace('$', "$$"), inputs));
+        return self;
+    }
+
+    pub fn generate(&self) -> String {
+        let mut content =
+            String::from("# Generated by cbuild; do not edit.\nninja_required_version = 1.3\n\n");
+        for rule in &self.rules {
+            content.push_str(rule);
+            content.push('\n');
+        }
+        if let Some((command, inputs)) = &self.regenerate {
+            content.push_str(&format!(
+                "rule regenerate\n  command = {}\n  description = Regenerating build.ninja\n  generator = 1\n\n",
+                command
+            ));
+            content.push_str(&format!(
+                "build build.ninja: regenerate {}\n\n",
+                escape_paths(inputs.iter().chain(&self.resources))
+            ));
+        } else if !self.resources.is_empty() {
+            event!(
+                Level::WARN,
+                "embedded resources are only refreshed by rerunning the generator; see `regenerate`"
+            );
+        }
+        for build in &self.builds {
+            content.push_str(build);
+        }
+        content.push_str(&format!("\ndefault {}\n", escape_paths(&self.defaults)));
+        content
+    }
+}
@@ -0,0 +1,489 @@
+use crate::compiler::{
+    CompilationFile, CompileCommand, CompileCommandBuilder, Compiler, DebugInfo, Lang,
+    OptimizationLevel, Std, Target,
+};
+use crate::json::Json;
+use crate::path::Normalize;
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+// An existing `compile_commands.json`, read back into a `CompileCommand`.
+#[derive(Debug, Clone)]
+pub struct CompilationDatabase {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    directory: PathBuf,
+    file: PathBuf,
+    arguments: Vec<String>,
+}
+
+// An argument from the database that has no equivalent in `CompileCommand`, or an
+// include dir or definition that differs from the one set every file is compiled with.
+// Options taking a separate value are reported together with it, as in `-x c`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unmapped {
+    file: PathBuf,
+    arg: String,
+}
+
+impl Unmapped {
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn arg(&self) -> &str {
+        &self.arg
+    }
+}
+
+impl std::fmt::Display for Unmapped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: `{}`", self.file.display(), self.arg)
+    }
+}
+
+pub struct Import {
+    builder: CompileCommandBuilder,
+    unmapped: Vec<Unmapped>,
+}
+
+impl Import {
+    pub fn unmapped(&self) -> &[Unmapped] {
+        &self.unmapped
+    }
+
+    // For further configuration (out dir, caches, ...) before building.
+    pub fn builder(self) -> CompileCommandBuilder {
+        self.builder
+    }
+
+    pub fn build(self) -> CompileCommand {
+        self.builder.build()
+    }
+}
+
+// Splits a `command` string the way the shell that ran it would have.
+// `cl` command lines keep backslashes, which are path separators there.
+fn split_command(command: &str) -> Vec<String> {
+    let windows = command
+        .split_whitespace()
+        .next()
+        .is_some_and(|program| is_msvc(program.trim_matches('"')));
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', Some('\'')) => current.push(c),
+            ('\\', _) if windows => match chars.peek() {
+                Some('"') => current.push(chars.next().unwrap_or('"')),
+                _ => current.push(c),
+            },
+            ('\\', _) => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            (q, None) if q == '"' || (q == '\'' && !windows) => {
+                quote = Some(q);
+                in_arg = true;
+            }
+            (q, Some(open)) if q == open => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (c, _) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+fn program_stem(program: &str) -> String {
+    let name = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    let name = name.to_ascii_lowercase();
+    return name.strip_suffix(".exe").unwrap_or(&name).to_string();
+}
+
+fn is_msvc(program: &str) -> bool {
+    let stem = program_stem(program);
+    return stem == "cl" || stem == "clang-cl";
+}
+
+fn compiler_for(program: &str) -> Compiler {
+    let stem = program_stem(program);
+    if is_msvc(program) {
+        return Compiler::Msvc;
+    }
+    if stem.contains("clang") {
+        return Compiler::Clang;
+    }
+    return Compiler::Gcc;
+}
+
+fn is_cpp(program: &str, file: &Path) -> bool {
+    if program_stem(program).ends_with("++") {
+        return true;
+    }
+    return file
+        .extension()
+        .is_some_and(|ext| ["cpp", "cc", "cxx", "c++", "C"].contains(&ext.to_str().unwrap_or("")));
+}
+
+// Settings gathered across all entries. `CompileCommand` applies one set of flags
+// to every file, so the first value seen wins and later conflicting ones are reported.
+// Include dirs and definitions are taken from the first entry as a whole; any other
+// entry that adds to or lacks one of them is reported with the flag as it was spelled.
+#[derive(Default)]
+struct Settings {
+    shared: Option<Vec<(Shared, String)>>,
+    std: Option<String>,
+    opt_level: Option<String>,
+    debug: Option<String>,
+    target: bool,
+}
+
+#[derive(PartialEq)]
+enum Shared {
+    Include(PathBuf),
+    Definition(String),
+}
+
+enum Mapped {
+    Include(String),
+    Definition(String),
+    Std(String),
+    OptLevel(String),
+    Debug(String),
+    Target,
+    // Handled by cbuild itself: `-c`, `-o`, depfiles and such.
+    Ignored,
+}
+
+// Takes the value of `flag` either glued to it (`-Ifoo`) or as the next argument (`-I foo`).
+fn flag_value(arg: &str, flag: &str, args: &mut std::slice::Iter<String>) -> Option<String> {
+    let rest = arg.strip_prefix(flag)?;
+    if !rest.is_empty() {
+        return Some(rest.to_string());
+    }
+    return args.next().cloned();
+}
+
+// Unmapped GNU options whose value is the next argument.
+const GNU_SEPARATE: [&str; 11] = [
+    "-include",
+    "-imacros",
+    "-idirafter",
+    "-isysroot",
+    "-x",
+    "-arch",
+    "-target",
+    "-Xclang",
+    "-Xpreprocessor",
+    "-Xassembler",
+    "-Xlinker",
+];
+
+fn map_gnu(arg: &str, args: &mut std::slice::Iter<String>) -> Option<Mapped> {
+    match arg {
+        "-c" | "-MMD" | "-MD" | "-MP" | "-pipe" => return Some(Mapped::Ignored),
+        "-o" | "-MF" | "-MT" | "-MQ" => {
+            args.next();
+            return Some(Mapped::Ignored);
+        }
+        arg if GNU_SEPARATE.contains(&arg) => {
+            args.next();
+            return None;
+        }
+        "-g" => return Some(Mapped::Debug(String::new())),
+        "-O" => return Some(Mapped::OptLevel("1".into())),
+        _ => {}
+    }
+    if arg.starts_with("-o") && arg.len() > 2 {
+        return Some(Mapped::Ignored);
+    }
+    for flag in ["-I", "-isystem", "-iquote"] {
+        if arg.starts_with(flag) {
+            return flag_value(arg, flag, args).map(Mapped::Include);
+        }
+    }
+    if arg.starts_with("-D") {
+        return flag_value(arg, "-D", args).map(Mapped::Definition);
+    }
+    if let Some(std) = arg.strip_prefix("-std=") {
+        return Some(Mapped::Std(std.to_string()));
+    }
+    if let Some(level) = arg.strip_prefix("-O") {
+        return Some(Mapped::OptLevel(level.to_string()));
+    }
+    if let Some(level) = arg.strip_prefix("-g") {
+        if level.chars().all(|c| c.is_ascii_digit()) {
+            return Some(Mapped::Debug(level.to_string()));
+        }
+    }
+    if let Some(target) = arg.strip_prefix("--target=") {
+        if target.starts_with("x86_64") {
+            return Some(Mapped::Target);
+        }
+    }
+    return None;
+}
+
+fn map_msvc(arg: &str, args: &mut std::slice::Iter<String>) -> Option<Mapped> {
+    // cl accepts both `/` and `-` as the option prefix.
+    let option = arg.strip_prefix('/').or_else(|| arg.strip_prefix('-'))?;
+    match option {
+        "c" | "nologo" | "showIncludes" | "FS" => return Some(Mapped::Ignored),
+        "Od" => return Some(Mapped::OptLevel("0".into())),
+        "O1" => return Some(Mapped::OptLevel("1".into())),
+        "O2" | "Ox" => return Some(Mapped::OptLevel("2".into())),
+        "Os" => return Some(Mapped::OptLevel("s".into())),
+        "Ot" => return Some(Mapped::OptLevel("fast".into())),
+        "Z7" | "Zi" | "ZI" => return Some(Mapped::Debug(String::new())),
+        _ => {}
+    }
+    if option.starts_with("Fo") || option.starts_with("Fd") {
+        return Some(Mapped::Ignored);
+    }
+    if option.starts_with('I') {
+        return flag_value(option, "I", args).map(Mapped::Include);
+    }
+    if option.starts_with('D') {
+        return flag_value(option, "D", args).map(Mapped::Definition);
+    }
+    if let Some(std) = option.strip_prefix("std:") {
+        return Some(Mapped::Std(std.to_string()));
+    }
+    return None;
+}
+
+fn std_for(std: &str) -> Option<Std> {
+    match std {
+        "c99" | "gnu99" | "c9x" | "gnu9x" | "iso9899:1999" => return Some(Std::C99),
+        "c11" | "gnu11" | "c1x" | "gnu1x" | "iso9899:2011" => return Some(Std::C11),
+        _ => return None,
+    }
+}
+
+fn opt_level_for(level: &str) -> Option<OptimizationLevel> {
+    match level {
+        "0" => return Some(OptimizationLevel::O0),
+        "1" => return Some(OptimizationLevel::O1),
+        "2" => return Some(OptimizationLevel::O2),
+        "3" => return Some(OptimizationLevel::O3),
+        "s" | "z" => return Some(OptimizationLevel::Small),
+        "fast" => return Some(OptimizationLevel::Fast),
+        _ => return None,
+    }
+}
+
+impl CompilationDatabase {
+    pub fn load(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                event!(Level::ERROR, "failed to read `{}`: `{}`", path.display(), e);
+                return None;
+            }
+        };
+        let database = Self::parse(&content);
+        if database.is_none() {
+            event!(
+                Level::ERROR,
+                "`{}` is not a compilation database",
+                path.display()
+            );
+        }
+        return database;
+    }
+
+    // Entries carry either an `arguments` array or a shell-quoted `command` string.
+    pub fn parse(content: &str) -> Option<Self> {
+        let json = Json::parse(content)?;
+        let mut entries = Vec::new();
+        for entry in json.as_array()? {
+            let directory = PathBuf::from(entry.get("directory")?.as_str()?);
+            let file = PathBuf::from(entry.get("file")?.as_str()?);
+            let arguments = match (entry.get("arguments"), entry.get("command")) {
+                (Some(arguments), _) => arguments
+                    .as_array()?
+                    .iter()
+                    .map(|arg| arg.as_str().map(String::from))
+                    .collect::<Option<Vec<_>>>()?,
+                (None, Some(command)) => split_command(command.as_str()?),
+                (None, None) => return None,
+            };
+            entries.push(Entry {
+                directory,
+                file,
+                arguments,
+            });
+        }
+        return Some(Self { entries });
+    }
+
+    pub fn files(&self) -> Vec<PathBuf> {
+        return self
+            .entries
+            .iter()
+            .map(|entry| entry.directory.join(&entry.file))
+            .collect();
+    }
+
+    // Paths are kept relative to the first entry's directory, which becomes the
+    // working directory of the command.
+    pub fn import(&self) -> Import {
+        let root = self
+            .entries
+            .first()
+            .map(|entry| entry.directory.clone())
+            .unwrap_or_else(|| PathBuf::from("."));
+        let relative = |dir: &Path, path: &str| -> PathBuf {
+            let path = dir.join(path).normalize();
+            return path
+                .strip_prefix(&root)
+                .map(Path::to_path_buf)
+                .unwrap_or(path);
+        };
+
+        let mut builder = CompileCommand::builder().working_dir(&root);
+        let mut settings = Settings::default();
+        let mut unmapped = Vec::new();
+        let mut compiler = None;
+        let mut cpp = false;
+        for entry in &self.entries {
+            let Some((program, args)) = entry.arguments.split_first() else {
+                continue;
+            };
+            let src = relative(&entry.directory, &entry.file.display().to_string());
+            compiler.get_or_insert_with(|| compiler_for(program));
+            cpp |= is_cpp(program, &src);
+
+            let msvc = is_msvc(program);
+            let mut shared = Vec::new();
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                if entry.directory.join(arg) == entry.directory.join(&entry.file) {
+                    continue;
+                }
+                let rest = args.as_slice();
+                let mapped = if msvc {
+                    map_msvc(arg, &mut args)
+                } else {
+                    map_gnu(arg, &mut args)
+                };
+                let value = &rest[..rest.len() - args.as_slice().len()];
+                let spelled = std::iter::once(arg)
+                    .chain(value)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let spelled = spelled.join(" ");
+                let conflict = match mapped {
+                    Some(Mapped::Include(dir)) => {
+                        let dir = Shared::Include(relative(&entry.directory, &dir));
+                        if !shared.iter().any(|(flag, _)| *flag == dir) {
+                            shared.push((dir, spelled.clone()));
+                        }
+                        false
+                    }
+                    Some(Mapped::Definition(def)) => {
+                        let def = Shared::Definition(def);
+                        if !shared.iter().any(|(flag, _)| *flag == def) {
+                            shared.push((def, spelled.clone()));
+                        }
+                        false
+                    }
+                    Some(Mapped::Std(std)) if std_for(&std).is_some() => {
+                        *settings.std.get_or_insert(std.clone()) != std
+                    }
+                    Some(Mapped::OptLevel(level)) if opt_level_for(&level).is_some() => {
+                        *settings.opt_level.get_or_insert(level.clone()) != level
+                    }
+                    Some(Mapped::Debug(level)) => {
+                        *settings.debug.get_or_insert(level.clone()) != level
+                    }
+                    Some(Mapped::Target) => {
+                        settings.target = true;
+                        false
+                    }
+                    Some(Mapped::Ignored) => false,
+                    Some(Mapped::Std(_)) | Some(Mapped::OptLevel(_)) | None => true,
+                };
+                if conflict {
+                    unmapped.push(Unmapped {
+                        file: src.clone(),
+                        arg: spelled,
+                    });
+                }
+            }
+            match &settings.shared {
+                None => settings.shared = Some(shared),
+                Some(first) => {
+                    let added = shared
+                        .iter()
+                        .filter(|(flag, _)| !first.iter().any(|(other, _)| other == flag));
+                    let lacking = first
+                        .iter()
+                        .filter(|(flag, _)| !shared.iter().any(|(other, _)| other == flag));
+                    for (_, arg) in added.chain(lacking) {
+                        unmapped.push(Unmapped {
+                            file: src.clone(),
+                            arg: arg.clone(),
+                        });
+                    }
+                }
+            }
+            builder = builder.file(CompilationFile::new(src));
+        }
+
+        if let Some(compiler) = compiler {
+            builder = builder.compiler(compiler);
+        }
+        if cpp {
+            builder = builder.kind(Lang::Cpp);
+        }
+        for (flag, _) in settings.shared.unwrap_or_default() {
+            builder = match flag {
+                Shared::Include(dir) => builder.include(dir),
+                Shared::Definition(def) => builder.definition(def),
+            };
+        }
+        if let Some(std) = settings.std.as_deref().and_then(std_for) {
+            builder = builder.std(std);
+        }
+        if let Some(level) = settings.opt_level.as_deref().and_then(opt_level_for) {
+            builder = builder.opt_level(level);
+        }
+        if let Some(level) = settings.debug {
+            builder = match level.as_str() {
+                "" => builder.debug_info(DebugInfo::Default),
+                level => match level.parse() {
+                    Ok(level) => builder.debug_info(DebugInfo::Level(level)),
+                    // `-ggdb` and friends have no portable spelling.
+                    Err(_) => builder.debug(format!("-g{}", level)),
+                },
+            };
+        }
+        if settings.target {
+            builder = builder.target(Target::X86_64);
+        }
+        for arg in &unmapped {
+            event!(Level::WARN, "couldn't map argument {}", arg);
+        }
+        return Import { builder, unmapped };
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    program: String,
+    args: Vec<String>,
+    cwd: PathBuf,
+    env: Vec<(String, String)>,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+}
+
+impl Invocation {
+    pub fn new(program: impl ToString) -> Self {
+        return Self {
+            program: program.to_string(),
+            args: Vec::new(),
+            cwd: PathBuf::from("."),
+            env: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+    }
+
+    pub fn arg(mut self, arg: impl ToString) -> Self {
+        self.args.push(arg.to_string());
+        return self;
+    }
+
+    pub fn args(mut self, args: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.to_string()));
+        return self;
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = cwd.into();
+        return self;
+    }
+
+    pub fn env(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        return self;
+    }
+
+    pub fn input(mut self, input: impl Into<PathBuf>) -> Self {
+        self.inputs.push(input.into());
+        return self;
+    }
+
+    pub fn inputs(mut self, inputs: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        self.inputs
+            .extend(inputs.into_iter().map(|input| input.into()));
+        return self;
+    }
+
+    pub fn output(mut self, output: impl Into<PathBuf>) -> Self {
+        self.outputs.push(output.into());
+        return self;
+    }
+
+    // Runs the current command line through `program`, e.g. a compiler cache or a debugger.
+    pub fn wrap(
+        mut self,
+        program: impl ToString,
+        args: impl IntoIterator<Item = impl ToString>,
+    ) -> Self {
+        let mut wrapped = args
+            .into_iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+        wrapped.push(std::mem::replace(&mut self.program, program.to_string()));
+        wrapped.append(&mut self.args);
+        self.args = wrapped;
+        return self;
+    }
+
+    pub fn get_program(&self) -> &str {
+        &self.program
+    }
+
+    pub fn get_args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn get_cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    pub fn get_env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    pub fn get_inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    pub fn get_outputs(&self) -> &[PathBuf] {
+        &self.outputs
+    }
+
+    pub fn to_command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.current_dir(&self.cwd);
+        cmd.args(&self.args);
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        cmd
+    }
+
+    // Stable multi-line rendering, one value per line, meant for snapshot tests.
+    pub fn snapshot(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!("program: {}\n", self.program));
+        out.push_str(&format!("cwd: {}\n", self.cwd.display()));
+        out.push_str("env:\n");
+        for (key, value) in &self.env {
+            out.push_str(&format!("  {}={}\n", key, value));
+        }
+        out.push_str("args:\n");
+        for arg in &self.args {
+            out.push_str(&format!("  {}\n", arg));
+        }
+        out.push_str("inputs:\n");
+        for input in &self.inputs {
+            out.push_str(&format!("  {}\n", input.display()));
+        }
+        out.push_str("outputs:\n");
+        for output in &self.outputs {
+            out.push_str(&format!("  {}\n", output.display()));
+        }
+        out
+    }
+}
+
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    return format!("'{}'", arg.replace('\'', "'\\''"));
+}
+
+impl std::fmt::Display for Invocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (key, value) in &self.env {
+            write!(f, "{}={} ", key, shell_quote(value))?;
+        }
+        write!(f, "{}", shell_quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", shell_quote(arg))?;
+        }
+        Ok(())
+    }
+}
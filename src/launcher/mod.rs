@@ -0,0 +1,108 @@
+use crate::toolchain::find_in_path;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Launcher {
+    Ccache,
+    Sccache,
+    Custom(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn since(&self, earlier: &CacheStats) -> CacheStats {
+        return CacheStats {
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+        };
+    }
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} hits, {} misses", self.hits, self.misses)
+    }
+}
+
+impl Launcher {
+    pub fn detect() -> Option<Self> {
+        if find_in_path("ccache").is_some() {
+            return Some(Self::Ccache);
+        }
+        if find_in_path("sccache").is_some() {
+            return Some(Self::Sccache);
+        }
+        None
+    }
+
+    pub fn program(&self) -> String {
+        match self {
+            Launcher::Ccache => return "ccache".into(),
+            Launcher::Sccache => return "sccache".into(),
+            Launcher::Custom(path) => return path.display().to_string(),
+        }
+    }
+
+    // Cumulative counters as reported by the launcher; `None` if it doesn't keep any.
+    pub fn stats(&self) -> Option<CacheStats> {
+        match self {
+            Launcher::Ccache => {
+                let out = Command::new("ccache").arg("--print-stats").output().ok()?;
+                if !out.status.success() {
+                    return None;
+                }
+                return Some(Self::parse_ccache_stats(&String::from_utf8_lossy(
+                    &out.stdout,
+                )));
+            }
+            Launcher::Sccache => {
+                let out = Command::new("sccache").arg("--show-stats").output().ok()?;
+                if !out.status.success() {
+                    return None;
+                }
+                return Some(Self::parse_sccache_stats(&String::from_utf8_lossy(
+                    &out.stdout,
+                )));
+            }
+            Launcher::Custom(_) => None,
+        }
+    }
+
+    // `ccache --print-stats` prints one tab-separated `key value` pair per line.
+    pub fn parse_ccache_stats(output: &str) -> CacheStats {
+        let mut stats = CacheStats::default();
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once('\t') else {
+                continue;
+            };
+            let value = value.trim().parse::<u64>().unwrap_or(0);
+            match key {
+                "direct_cache_hit" | "preprocessed_cache_hit" => stats.hits += value,
+                "cache_miss" => stats.misses += value,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    // `sccache --show-stats` aligns `Cache hits` / `Cache misses` totals in columns,
+    // followed by per-language breakdowns that are skipped here.
+    pub fn parse_sccache_stats(output: &str) -> CacheStats {
+        let mut stats = CacheStats::default();
+        for line in output.lines() {
+            let total = |prefix: &str| line.strip_prefix(prefix)?.trim().parse::<u64>().ok();
+            if let Some(hits) = total("Cache hits") {
+                stats.hits = hits;
+            } else if let Some(misses) = total("Cache misses") {
+                stats.misses = misses;
+            }
+        }
+        stats
+    }
+}
@@ -1,12 +1,34 @@
+pub mod cache;
+pub mod cargo;
+pub mod checks;
+pub mod cli;
 pub mod compiler;
+pub mod coverage;
+pub mod embed;
 pub mod generator;
+mod hash;
+pub mod import;
+pub mod invocation;
+mod json;
+pub mod launcher;
 pub mod linker;
 mod path;
+pub mod pgo;
+pub mod step;
+pub mod testing;
+pub mod toolchain;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use compiler::CompilationFile;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     #[test]
     fn test() {
@@ -16,4 +38,1742 @@ mod tests {
 
         command.run();
     }
+
+    #[test]
+    fn invocation() {
+        let command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .out_dir("build")
+            .file(CompilationFile::new("src/main.c"))
+            .opt_level(compiler::OptimizationLevel::O2)
+            .include("my include")
+            .build();
+
+        let invocation = command.build_command_for_file(&command.files()[0]);
+        assert_eq!(invocation, command.invocations()[0]);
+        assert_eq!(
+            invocation.to_string(),
+            "gcc -c src/main.c -o build/obj/src/main.o -O2 -I 'my include' -MMD -MF build/obj/src/main.o.d"
+        );
+        assert_eq!(
+            invocation.get_outputs(),
+            [PathBuf::from("build/obj/src/main.o")]
+        );
+    }
+
+    #[test]
+    fn custom_toolchain() {
+        #[derive(Debug)]
+        struct Tcc;
+
+        impl toolchain::Toolchain for Tcc {
+            fn name(&self) -> &str {
+                "tcc"
+            }
+
+            fn compiler(&self, _lang: &compiler::Lang) -> &str {
+                "tcc"
+            }
+        }
+
+        let command = compiler::CompileCommand::builder()
+            .toolchain(Tcc)
+            .file(CompilationFile::new("main.c"))
+            .std(compiler::Std::C99)
+            .definition("NDEBUG".into())
+            .build();
+
+        assert_eq!(
+            command.invocations()[0].to_string(),
+            "tcc -c main.c -o ./obj/main.o -std=c99 -DNDEBUG -MMD -MF ./obj/main.o.d"
+        );
+    }
+
+    #[test]
+    fn toolchain_flags() {
+        let flags = |compiler: compiler::Compiler, opt: compiler::OptimizationLevel| {
+            compiler::CompileCommand::builder()
+                .compiler(compiler)
+                .opt_level(opt)
+                .file(CompilationFile::new("main.c"))
+                .build()
+                .flags()
+        };
+        // GCC and Clang reject `-OFast`; the level is spelled `-Ofast`.
+        assert_eq!(
+            flags(compiler::Compiler::Gcc, compiler::OptimizationLevel::Fast),
+            ["-Ofast"]
+        );
+        assert_eq!(
+            flags(compiler::Compiler::Msvc, compiler::OptimizationLevel::Fast),
+            ["/Ot"]
+        );
+
+        let clang = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Clang)
+            .target(compiler::Target::X86_64)
+            .file(CompilationFile::new("main.c"))
+            .build();
+        #[cfg(target_os = "linux")]
+        assert_eq!(clang.flags(), ["--target=x86_64-pc-linux-gnu"]);
+
+        let debug = |compiler: compiler::Compiler| {
+            compiler::CompileCommand::builder()
+                .compiler(compiler)
+                .debug_info(compiler::DebugInfo::Level(3))
+                .file(CompilationFile::new("main.c"))
+                .build()
+                .flags()
+        };
+        assert_eq!(debug(compiler::Compiler::Gcc), ["-g3"]);
+        assert_eq!(debug(compiler::Compiler::Msvc), ["/Z7"]);
+        // The untyped form passes its flag through untouched.
+        let verbatim = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .debug("-g".into())
+            .file(CompilationFile::new("main.c"))
+            .build();
+        assert_eq!(verbatim.flags(), ["-g"]);
+
+        let ld = compiler::CompileCommand::builder()
+            .toolchain(toolchain::Ld::new("ld"))
+            .file(CompilationFile::new("main.c"))
+            .build();
+        assert_eq!(ld.compiler(), "gcc");
+        let lld = compiler::CompileCommand::builder()
+            .toolchain(toolchain::Ld::new("lld").driver(&toolchain::Clang))
+            .kind(compiler::Lang::Cpp)
+            .file(CompilationFile::new("main.cpp"))
+            .build();
+        assert_eq!(lld.compiler(), "clang++");
+
+        // Without a linker, linking goes through the compile command's driver.
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .file(CompilationFile::new("main.c"))
+            .build();
+        let link = linker::LinkerCommand::builder(&mut command).build();
+        assert_eq!(link.build_command().get_program(), "gcc");
+    }
+
+    #[test]
+    fn toolchain_probe() {
+        use toolchain::{Family, ToolchainInfo, Version};
+        assert_eq!(
+            Version::find("gcc (Debian 12.2.0-14) 12.2.0"),
+            Some(Version::new(12, 2, 0))
+        );
+        assert_eq!(
+            Version::find("Microsoft (R) C/C++ Optimizing Compiler Version 19.38.33135 for x64"),
+            Some(Version::new(19, 38, 33135))
+        );
+        assert_eq!(
+            Version::find("Apple clang 15.0"),
+            Some(Version::new(15, 0, 0))
+        );
+        assert_eq!(Version::find("tcc version unknown"), None);
+        assert!(Version::new(4, 10, 0) > Version::new(4, 9, 3));
+
+        let info = |family, major, minor| {
+            ToolchainInfo::new("cc", family, Version::new(major, minor, 0), "")
+        };
+        assert!(info(Family::Gcc, 4, 6).supports_std(&compiler::Std::C99));
+        assert!(!info(Family::Gcc, 4, 6).supports_std(&compiler::Std::C11));
+        assert!(info(Family::Gcc, 4, 7).supports_std(&compiler::Std::C11));
+        assert!(!info(Family::Clang, 3, 0).supports_std(&compiler::Std::C11));
+        assert!(!info(Family::Msvc, 19, 38).supports_std(&compiler::Std::C99));
+        assert!(info(Family::Msvc, 19, 28).supports_std(&compiler::Std::C11));
+
+        let cross = ToolchainInfo::new(
+            "/opt/cross/bin/aarch64-linux-gnu-gcc",
+            Family::Gcc,
+            Version::new(12, 2, 0),
+            "aarch64-linux-gnu",
+        );
+        let command = compiler::CompileCommand::builder()
+            .toolchain_info(cross.clone())
+            .kind(compiler::Lang::Cpp)
+            .file(CompilationFile::new("main.cpp"))
+            .build();
+        assert_eq!(command.compiler(), "/opt/cross/bin/aarch64-linux-gnu-g++");
+        assert_eq!(command.toolchain().name(), "gcc");
+
+        // Detection waits for `build()`, so the cache lands in the final out dir.
+        let dir = std::env::temp_dir().join(format!("cbuild-probe-{}", std::process::id()));
+        let command = compiler::CompileCommand::builder()
+            .detect_toolchain()
+            .out_dir(&dir)
+            .build();
+        if let Some(info) = command.toolchain_info() {
+            assert!(dir.join("toolchain.cache").exists());
+            assert_eq!(command.compiler(), info.program().to_str().unwrap());
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn builtins_parsing() {
+        let verbose = "ignoring nonexistent directory \"/usr/local/include/x86_64-linux-gnu\"\n\
+                       #include \"...\" search starts here:\n \
+                       /src/quoted\n\
+                       #include <...> search starts here:\n \
+                       /usr/lib/gcc/x86_64-linux-gnu/12/include\n \
+                       /usr/include\n\
+                       End of search list.\n";
+        assert_eq!(
+            toolchain::Builtins::parse_include_dirs(verbose),
+            [
+                PathBuf::from("/usr/lib/gcc/x86_64-linux-gnu/12/include"),
+                PathBuf::from("/usr/include")
+            ]
+        );
+        let macros = toolchain::Builtins::parse_macros(
+            "#define __GNUC__ 12\n#define __STDC__ 1\n#define __unix\n",
+        );
+        assert_eq!(
+            macros,
+            [
+                ("__GNUC__".to_string(), "12".to_string()),
+                ("__STDC__".to_string(), "1".to_string()),
+                ("__unix".to_string(), String::new())
+            ]
+        );
+        let builtins = toolchain::Builtins::new(vec![PathBuf::from("/usr/include")], macros);
+        assert_eq!(builtins.args(), ["-isystem", "/usr/include", "-D__unix="]);
+    }
+
+    #[test]
+    fn precompiled_header() {
+        let dir = std::env::temp_dir().join(format!("cbuild-pch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("base.h"), "#define VALUE 1\n").unwrap();
+        std::fs::write(dir.join("common.h"), "#include \"base.h\"\n").unwrap();
+        std::fs::write(dir.join("main.c"), "int main(void) { return VALUE - 1; }\n").unwrap();
+        let build = || {
+            let mut command = compiler::CompileCommand::builder()
+                .compiler(compiler::Compiler::Gcc)
+                .working_dir(&dir)
+                .out_dir("out")
+                .pch("common.h")
+                .file(CompilationFile::new("main.c"))
+                .build();
+            assert!(matches!(command.run(), compiler::Status::Success));
+        };
+        let modified = || {
+            let pch = dir.join("out/pch/common.h.gch");
+            pch.metadata().unwrap().modified().unwrap()
+        };
+        build();
+        let first = modified();
+        build();
+        assert_eq!(modified(), first);
+
+        // Only the header the PCH includes changes.
+        let later = first + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("base.h"))
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        build();
+        assert!(modified() > first);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unity_build() {
+        let dir = std::env::temp_dir().join(format!("cbuild-unity-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a.c"),
+            "static int value(void) { return 1; }\nint a(void) { return value(); }\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("b.c"), "int b(void) { return 2; }\n").unwrap();
+        // Clashes with `a.c`'s static `value` when batched together.
+        std::fs::write(
+            dir.join("c.c"),
+            "static int value(void) { return 3; }\nint c(void) { return value(); }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "int a(void); int b(void); int c(void);\nint main(void) { return a() + b() + c() - 6; }\n",
+        )
+        .unwrap();
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .unity_exclude("c.c")
+            .unity(2)
+            .files(["a.c", "b.c", "c.c", "main.c"].map(CompilationFile::new))
+            .build();
+        let units = command
+            .units()
+            .iter()
+            .map(|unit| (unit.src().to_path_buf(), unit.deps().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            units,
+            [
+                (
+                    PathBuf::from("out/unity/unity_0.c"),
+                    vec![PathBuf::from("a.c"), PathBuf::from("b.c")]
+                ),
+                (
+                    PathBuf::from("out/unity/unity_1.c"),
+                    vec![PathBuf::from("main.c")]
+                ),
+                (PathBuf::from("c.c"), vec![]),
+            ]
+        );
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let mut link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        assert_eq!(link.execute(|run| run), Some(0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_escapes() {
+        let string = |text: &str| json::Json::parse(text)?.as_str().map(String::from);
+        assert_eq!(string(r#""\ud83d\ude00""#).unwrap(), "\u{1f600}");
+        assert_eq!(string(r#""\u00e9\u00E9""#).unwrap(), "\u{e9}\u{e9}");
+        // A high surrogate without its low half doesn't swallow the next escape.
+        assert_eq!(string(r#""\ud800\u0041""#).unwrap(), "\u{fffd}A");
+        assert_eq!(string(r#""\ud800x""#).unwrap(), "\u{fffd}x");
+        assert_eq!(string(r#""\u+abc""#), None);
+    }
+
+    #[test]
+    fn compilation_database() {
+        let database = import::CompilationDatabase::parse(
+            r#"[
+                {"directory": "/src/proj", "file": "main.c",
+                 "command": "gcc -c -std=c11 -O2 -I include -DNAME=\"a b\" -include config.h -x c -Wall main.c -o main.o"},
+                {"directory": "/src/proj/lib", "file": "util.c",
+                 "arguments": ["gcc", "-c", "-std=c99", "-I../include", "-DFOO", "util.c"]}
+            ]"#,
+        )
+        .unwrap();
+        let import = database.import();
+        assert_eq!(
+            import
+                .unmapped()
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "main.c: `-include config.h`",
+                "main.c: `-x c`",
+                "main.c: `-Wall`",
+                "lib/util.c: `-std=c99`",
+                // Only the first entry's definitions are applied to every file.
+                "lib/util.c: `-DFOO`",
+                "lib/util.c: `-DNAME=a b`",
+            ]
+        );
+        let command = import.builder().compiler(compiler::Compiler::Gcc).build();
+        assert_eq!(command.includes(), [PathBuf::from("include")]);
+        assert_eq!(command.definitions(), ["NAME=a b"]);
+        assert_eq!(
+            command
+                .build_command_for_file(&command.files()[1])
+                .to_string(),
+            "gcc -c lib/util.c -o ./obj/lib/util.o -O2 -std=c11 -I include '-DNAME=a b' -MMD -MF ./obj/lib/util.o.d"
+        );
+
+        let msvc = import::CompilationDatabase::parse(
+            r#"[{"directory": "C:/proj", "file": "main.c",
+                 "command": "cl.exe /nologo /c /Od /Zi /I C:\\proj\\inc /DWIN32 main.c"}]"#,
+        )
+        .unwrap()
+        .import();
+        assert!(msvc.unmapped().is_empty());
+        let command = msvc.build();
+        assert_eq!(command.compiler(), "cl");
+        assert_eq!(command.definitions(), ["WIN32"]);
+    }
+
+    #[test]
+    fn launcher() {
+        let command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .launcher(launcher::Launcher::Ccache)
+            .file(CompilationFile::new("main.c"))
+            .build();
+        let file = &command.files()[0];
+        assert_eq!(
+            command.build_command_for_file(file).to_string(),
+            "ccache gcc -c main.c -o ./obj/main.o -MMD -MF ./obj/main.o.d"
+        );
+        assert_eq!(command.raw_command_for_file(file).get_program(), "gcc");
+
+        let stats = launcher::Launcher::parse_ccache_stats(
+            "cache_miss\t3\ndirect_cache_hit\t4\npreprocessed_cache_hit\t1\n",
+        );
+        assert_eq!((stats.hits, stats.misses), (5, 3));
+    }
+
+    type Entries = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    fn spawn_cache_server() -> (String, Entries, Arc<AtomicUsize>) {
+        spawn_cache_server_on(TcpListener::bind("127.0.0.1:0").unwrap())
+    }
+
+    // Minimal stand-in for a file-serving cache: GET/PUT against an in-memory map,
+    // counting the GETs that found an entry.
+    fn spawn_cache_server_on(listener: TcpListener) -> (String, Entries, Arc<AtomicUsize>) {
+        let url = format!("http://{}/cache", listener.local_addr().unwrap());
+        let store = Arc::new(Mutex::new(HashMap::new()));
+        let entries = store.clone();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let mut parts = request.split(' ');
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap().to_string());
+                let response = match method {
+                    "PUT" => {
+                        entries.lock().unwrap().insert(path, body);
+                        b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n".to_vec()
+                    }
+                    _ => match entries.lock().unwrap().get(&path) {
+                        Some(data) => {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                                data.len()
+                            )
+                            .into_bytes();
+                            response.extend(data);
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                    },
+                };
+                stream.write_all(&response).unwrap();
+            }
+        });
+        (url, store, hits)
+    }
+
+    #[test]
+    fn remote_cache() {
+        let (url, store, _) = spawn_cache_server();
+        let remote = cache::RemoteCache::new(&url).unwrap();
+        assert_eq!(remote.get("abc"), None);
+        assert!(remote.put("abc", b"object"));
+        assert_eq!(remote.get("abc").as_deref(), Some(&b"object"[..]));
+        assert!(store.lock().unwrap().contains_key("/cache/abc"));
+
+        let read_only = remote.clone().mode(cache::RemoteMode::ReadOnly);
+        assert!(!read_only.put("def", b"object"));
+        assert_eq!(read_only.get("def"), None);
+        assert!(!remote.is_offline());
+
+        // Bracketed IPv6 hosts keep their port, where the sandbox allows binding one.
+        if let Ok(listener) = TcpListener::bind("[::1]:0") {
+            let (url, _, _) = spawn_cache_server_on(listener);
+            assert!(url.starts_with("http://[::1]:"));
+            let remote = cache::RemoteCache::new(&url).unwrap();
+            assert!(remote.put("abc", b"object"));
+            assert_eq!(remote.get("abc").as_deref(), Some(&b"object"[..]));
+        }
+        assert!(cache::RemoteCache::new("http://::1:8080").is_none());
+    }
+
+    #[test]
+    fn remote_cache_unreachable() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let remote = cache::RemoteCache::new(&format!("http://127.0.0.1:{}", port))
+            .unwrap()
+            .timeout(Duration::from_millis(200));
+        assert_eq!(remote.get("abc"), None);
+        assert!(remote.is_offline());
+        assert!(!remote.put("abc", b"object"));
+    }
+
+    #[test]
+    fn cache_key_hash() {
+        let digest = |input: &[u8]| {
+            let mut hasher = hash::Hasher::new();
+            hasher.update(input);
+            hasher.finish()
+        };
+        assert_eq!(
+            digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            digest(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn object_cache_eviction() {
+        let dir = std::env::temp_dir().join(format!("cbuild-lru-{}", std::process::id()));
+        let cache = cache::ObjectCache::new(&dir).max_size(10);
+        let tick = || std::thread::sleep(Duration::from_millis(20));
+        cache.put_bytes("aa01", b"aaaa");
+        tick();
+        cache.put_bytes("bb02", b"bbbb");
+        tick();
+        // A hit makes `aa01` the most recently used entry.
+        assert!(cache.get("aa01", &dir.join("restored")));
+        tick();
+        cache.put_bytes("cc03", b"cccc");
+        assert_eq!(cache.size(), 12);
+
+        cache.evict();
+        assert_eq!(cache.size(), 8);
+        assert_eq!(cache.get_bytes("bb02"), None);
+        assert!(cache.get_bytes("aa01").is_some());
+        assert!(cache.get_bytes("cc03").is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_tool_aborts() {
+        let dir = std::env::temp_dir().join(format!("cbuild-missing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.c"), "int main(void) { return 0; }\n").unwrap();
+        let cache = cache::ObjectCache::new(dir.join("cache"));
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .file(CompilationFile::new("main.c"))
+            .launcher(launcher::Launcher::Custom(dir.join("no-such-launcher")))
+            .cache(cache.clone())
+            .build();
+        assert!(matches!(command.run(), compiler::Status::Aborted));
+        assert_eq!(cache.size(), 0);
+
+        // Same for a linker that isn't installed: no artifact, nothing cached.
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .file(CompilationFile::new("main.c"))
+            .cache(cache.clone())
+            .build();
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let objects = cache.size();
+        let mut link = linker::LinkerCommand::builder(&mut command)
+            .toolchain(toolchain::Ld::new("no-such-linker"))
+            .name("app")
+            .build();
+        assert!(matches!(link.run(), compiler::Status::Aborted));
+        assert_eq!(cache.size(), objects);
+        assert_eq!(link.execute(|run| run), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remote_cache_compile() {
+        let (url, store, hits) = spawn_cache_server();
+        let dir = std::env::temp_dir().join(format!("cbuild-remote-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.h"), "#define CODE 0\n").unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include \"app.h\"\nint main(void) { return CODE; }\n",
+        )
+        .unwrap();
+
+        // Each round is a fresh checkout with a cold local cache; only the remote persists.
+        for round in 0..2 {
+            std::fs::remove_dir_all(dir.join("out")).ok();
+            let local = cache::ObjectCache::new(dir.join(format!("local-{}", round)));
+            let mut command = compiler::CompileCommand::builder()
+                .compiler(compiler::Compiler::Gcc)
+                .working_dir(&dir)
+                .out_dir("out")
+                .file(CompilationFile::new("main.c"))
+                .coverage(true)
+                .cache(local)
+                .remote_cache(cache::RemoteCache::new(&url).unwrap())
+                .build();
+            assert!(matches!(command.run(), compiler::Status::Success));
+            // Restored objects come back with their depfile and coverage notes.
+            assert_eq!(command.headers(), [PathBuf::from("app.h")]);
+            assert!(dir.join("out/obj/main.gcno").exists());
+            let mut app = linker::LinkerCommand::builder(&mut command)
+                .linker(linker::Linker::Gcc)
+                .name("app")
+                .build();
+            assert!(matches!(app.run(), compiler::Status::Success));
+            assert_eq!(app.execute(|run| run), Some(0));
+            assert_eq!(hits.load(Ordering::SeqCst), round * 4);
+        }
+        // The object with its depfile and notes, and the executable.
+        assert_eq!(store.lock().unwrap().len(), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn configure_checks() {
+        let dir = std::env::temp_dir().join(format!("cbuild-checks-{}", std::process::id()));
+        let command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .build();
+        let mut checks = checks::Checks::new(&command).lib("m");
+        assert!(checks.has_header("stdio.h"));
+        assert!(!checks.has_header("no_such_header.h"));
+        assert!(checks.has_function("cos"));
+        assert!(!checks.has_function("no_such_function"));
+        assert_eq!(checks.sizeof("int32_t"), Some(4));
+        assert_eq!(checks.sizeof("char[37]"), Some(37));
+        assert_eq!(checks.sizeof("no_such_type"), None);
+        assert_eq!(checks.sizeof("char[100000]"), None);
+        assert!(checks.accepts_flag("-Wall"));
+        assert!(!checks.accepts_flag("-fno-such-flag"));
+
+        let cached = std::fs::read_dir(dir.join("out/checks"))
+            .unwrap()
+            .flatten()
+            .find(|entry| entry.file_name().to_string_lossy().ends_with(".cache"))
+            .unwrap();
+        assert!(std::fs::read_to_string(cached.path())
+            .unwrap()
+            .contains("sizeof char[37]=37\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_header() {
+        let header = checks::ConfigHeader::new("config.h")
+            .define("VERSION", "\"1.2\"")
+            .flag("HAVE_UNISTD_H", true)
+            .flag("HAVE_WINDOWS_H", false)
+            .define("USE_THREADS", "OFF");
+        assert_eq!(
+            header.render_template(
+                "#define PROJECT_VERSION @VERSION@\n\
+                 #cmakedefine HAVE_UNISTD_H\n\
+                 #cmakedefine HAVE_WINDOWS_H 1\n\
+                 #  cmakedefine USE_THREADS\n\
+                 #cmakedefine01 HAVE_UNISTD_H\n\
+                 contact@example.com @UNSET@\n"
+            ),
+            "#define PROJECT_VERSION \"1.2\"\n\
+             #define HAVE_UNISTD_H\n\
+             /* #undef HAVE_WINDOWS_H */\n\
+             /* #undef USE_THREADS */\n\
+             #define HAVE_UNISTD_H 1\n\
+             contact@example.com \n"
+        );
+
+        let dir = std::env::temp_dir().join(format!("cbuild-config-{}", std::process::id()));
+        let mut command = compiler::CompileCommand::builder()
+            .working_dir(&dir)
+            .out_dir("out")
+            .build();
+        let path = command.config_header(&header).unwrap();
+        assert_eq!(path, PathBuf::from("out/include/config.h"));
+        assert_eq!(command.includes(), [PathBuf::from("out/include")]);
+        let written = std::fs::metadata(dir.join(&path))
+            .unwrap()
+            .modified()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        command.config_header(&header).unwrap();
+        assert_eq!(
+            std::fs::metadata(dir.join(&path))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            written
+        );
+        assert_eq!(command.includes().len(), 1);
+        assert!(std::fs::read_to_string(dir.join(&path))
+            .unwrap()
+            .contains("#define HAVE_UNISTD_H 1\n/* #undef HAVE_WINDOWS_H */\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn embed_resources() {
+        for mode in [embed::EmbedMode::Array, embed::EmbedMode::Incbin] {
+            let dir = std::env::temp_dir().join(format!("cbuild-embed-{}", std::process::id()));
+            std::fs::create_dir_all(dir.join("assets")).unwrap();
+            std::fs::write(dir.join("assets/greeting.txt"), "hello").unwrap();
+            std::fs::write(
+                dir.join("main.c"),
+                "#include <stdio.h>\n#include \"greeting_txt.h\"\n\
+                 int main(void) { printf(\"%s %d\", greeting_txt, (int)greeting_txt_len); return 0; }\n",
+            )
+            .unwrap();
+
+            // The last edit keeps the length, so only the resource's contents change.
+            for (content, expected) in [
+                ("hello", "hello 5"),
+                ("goodbye", "goodbye 7"),
+                ("welcome", "welcome 7"),
+            ] {
+                std::fs::write(dir.join("assets/greeting.txt"), content).unwrap();
+                std::thread::sleep(Duration::from_millis(20));
+                let mut command = compiler::CompileCommand::builder()
+                    .compiler(compiler::Compiler::Gcc)
+                    .working_dir(&dir)
+                    .out_dir("out")
+                    .file(CompilationFile::new("main.c"))
+                    .embed("assets/greeting.txt")
+                    .embed_mode(mode)
+                    .cache(cache::ObjectCache::new(dir.join("cache")))
+                    .build();
+                assert!(matches!(command.run(), compiler::Status::Success));
+                let mut link = linker::LinkerCommand::builder(&mut command)
+                    .linker(linker::Linker::Gcc)
+                    .name("app")
+                    .build();
+                assert!(matches!(link.run(), compiler::Status::Success));
+                let out = std::process::Command::new(dir.join("out/app"))
+                    .output()
+                    .unwrap();
+                assert_eq!(String::from_utf8_lossy(&out.stdout), expected);
+            }
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    // A small project with a header, for the build file generators.
+    fn generator_project(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cbuild-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("include")).unwrap();
+        std::fs::write(dir.join("include/util.h"), "int util(void);\n").unwrap();
+        std::fs::write(
+            dir.join("util.c"),
+            "#include \"util.h\"\nint util(void) { return 0; }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include \"util.h\"\nint main(void) { return util(); }\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    fn generator_command(dir: &PathBuf, out_dir: &str) -> compiler::CompileCommand {
+        compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(dir)
+            .out_dir(out_dir)
+            .file(CompilationFile::new("main.c"))
+            .file(CompilationFile::new("util.c"))
+            .include("include")
+            .build()
+    }
+
+    #[test]
+    fn ninja_generator() {
+        let dir = generator_project("ninja");
+        let mut command = generator_command(&dir, "out");
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        let content = generator::NinjaGenerator::new().link(&link).generate();
+        for line in [
+            "  command = gcc -c $in -o $out -I include -MMD -MF $out.d\n",
+            "  deps = gcc\n  depfile = $out.d\n",
+            "  command = gcc -o out/app out/obj/main.o out/obj/util.o\n",
+            "build out/obj/main.o: cc_0 main.c\n",
+            "build out/obj/util.o: cc_0 util.c\n",
+            "build out/app: link_1 out/obj/main.o out/obj/util.o\n",
+            "default out/app\n",
+        ] {
+            assert!(
+                content.contains(line),
+                "missing {:?} in:\n{}",
+                line,
+                content
+            );
+        }
+        // Only emitted when the caller says how to rerun the generator.
+        assert!(!content.contains("regenerate"));
+        let content = generator::NinjaGenerator::new()
+            .link(&link)
+            .regenerate(["cargo", "run", "--", "gen dir"], ["build.rs"])
+            .generate();
+        assert!(content.contains("  command = cargo run -- 'gen dir'\n  description"));
+        assert!(content.contains("build build.ninja: regenerate build.rs\n"));
+
+        if toolchain::find_in_path("ninja").is_some() {
+            std::fs::write(
+                dir.join("build.ninja"),
+                generator::NinjaGenerator::new().link(&link).generate(),
+            )
+            .unwrap();
+            let ninja = || {
+                std::process::Command::new("ninja")
+                    .current_dir(&dir)
+                    .output()
+                    .unwrap()
+            };
+            assert!(ninja().status.success());
+            assert!(std::process::Command::new(dir.join("out/app"))
+                .status()
+                .unwrap()
+                .success());
+            let out = ninja();
+            assert!(String::from_utf8_lossy(&out.stdout).contains("no work to do"));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn make_generator() {
+        let dir = generator_project("make");
+        let mut command = generator_command(&dir, "out $dir");
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        let content = generator::MakefileGenerator::new(&link).generate();
+        for line in [
+            "out\\ $$dir/app: out\\ $$dir/obj/main.o out\\ $$dir/obj/util.o\n",
+            "out\\ $$dir/obj/main.o: main.c\n",
+            "\trm -f 'out $$dir/obj/main.o' 'out $$dir/obj/util.o' 'out $$dir/app'",
+            "\tinstall -m 755 'out $$dir/app' ",
+        ] {
+            assert!(
+                content.contains(line),
+                "missing {:?} in:\n{}",
+                line,
+                content
+            );
+        }
+
+        // Colons are escaped in rules but not in `include`.
+        let mut command = generator_command(&dir, "out:1");
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        let colons = generator::MakefileGenerator::new(&link).generate();
+        assert!(colons.contains("out\\:1/obj/main.o: main.c\n"));
+        assert!(colons.contains("-include out:1/obj/main.o.d out:1/obj/util.o.d\n"));
+        assert!(!content.contains("GNU make 4.3"));
+
+        // Grouped targets need GNU make 4.3, which the header states.
+        let mut command = compiler::CompileCommand::builder()
+            .working_dir(&dir)
+            .file(CompilationFile::new("main.c"))
+            .step(step::CustomStep::new(
+                "pair",
+                invocation::Invocation::new("true")
+                    .output("gen/a.c")
+                    .output("gen/a.h"),
+            ))
+            .build();
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        let grouped = generator::MakefileGenerator::new(&link).generate();
+        assert!(grouped.contains("gen/a.c gen/a.h&: \n"));
+        assert!(grouped.starts_with(
+            "# Generated by cbuild; do not edit.\n# Requires GNU make 4.3 or newer.\n"
+        ));
+
+        // A DLL installs to `bin` and its import library to `lib`; other shared
+        // libraries go to `lib`.
+        let mut command = generator_command(&dir, "out");
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Link)
+            .bin_type(linker::BinType::DynamicLib)
+            .name("app")
+            .build();
+        let shared = generator::MakefileGenerator::new(&link).generate();
+        if cfg!(target_os = "windows") {
+            for line in [
+                "\tinstall -m 755 out/app.dll \"$(DESTDIR)$(PREFIX)/bin/\"\n",
+                "\tinstall -m 644 out/app.dll.lib \"$(DESTDIR)$(PREFIX)/lib/\"\n",
+                " out/app.dll out/app.dll.lib ",
+            ] {
+                assert!(shared.contains(line), "missing {:?} in:\n{}", line, shared);
+            }
+        } else {
+            assert!(shared.contains("\tinstall -m 755 out/app.so \"$(DESTDIR)$(PREFIX)/lib/\"\n"));
+            assert!(!shared.contains("/bin"));
+        }
+
+        if toolchain::find_in_path("make").is_none() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        std::fs::write(dir.join("Makefile"), content).unwrap();
+        let make = |args: &[&str]| {
+            std::process::Command::new("make")
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+        };
+        let dry = make(&["-n"]);
+        assert!(dry.status.success());
+        assert!(!dir.join("out $dir").exists());
+        assert!(make(&[]).status.success());
+        assert!(std::process::Command::new(dir.join("out $dir/app"))
+            .status()
+            .unwrap()
+            .success());
+        // Up to date until a header the depfiles recorded changes.
+        assert!(make(&["-q"]).status.success());
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(dir.join("include/util.h"), "int util(void);\n\n").unwrap();
+        assert!(!make(&["-q"]).status.success());
+        assert!(make(&["clean"]).status.success());
+        assert!(!dir.join("out $dir/app").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cmake_package() {
+        let dir = generator_project("cmake");
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .file(CompilationFile::new("util.c"))
+            .include("include")
+            .include("src")
+            .definition("USE_UTIL=1".into())
+            .build();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .bin_type(linker::BinType::StaticLib)
+            .name("libutil")
+            .link("m")
+            .build();
+        let absolute = |path: &str| std::path::absolute(dir.join(path)).unwrap();
+        let config = generator::CMakePackageGenerator::new(&link, "Util", "1.2.0").config();
+        for line in [
+            "  add_library(Util::Util STATIC IMPORTED)\n".to_string(),
+            format!(
+                "    IMPORTED_LOCATION \"{}\"\n",
+                absolute("out/libutil.a").display()
+            ),
+            format!(
+                "    INTERFACE_INCLUDE_DIRECTORIES \"{}\"\n",
+                absolute("include").display()
+            ),
+            "    INTERFACE_LINK_LIBRARIES \"m\"\n".to_string(),
+        ] {
+            assert!(config.contains(&line), "missing {:?} in:\n{}", line, config);
+        }
+        // Private include directories and definitions aren't exported.
+        assert!(!config.contains("src"));
+        assert!(!config.contains("USE_UTIL"));
+        let headerless = generator::CMakePackageGenerator::new(&link, "Util", "1.2.0")
+            .include_dir(None)
+            .config();
+        assert!(!headerless.contains("INTERFACE_INCLUDE_DIRECTORIES"));
+        let installed = generator::CMakePackageGenerator::new(&link, "Util", "1.2.0")
+            .install_tree(true)
+            .config();
+        assert!(installed.contains("    IMPORTED_LOCATION \"${_IMPORT_PREFIX}/lib/libutil.a\"\n"));
+        assert!(
+            installed.contains("    INTERFACE_INCLUDE_DIRECTORIES \"${_IMPORT_PREFIX}/include\"\n")
+        );
+
+        // DLLs install as runtime files in `bin`, their import libraries in `lib`.
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Link)
+            .bin_type(linker::BinType::DynamicLib)
+            .name("util")
+            .build();
+        let shared = generator::CMakePackageGenerator::new(&link, "Util", "1.2.0")
+            .install_tree(true)
+            .config();
+        if cfg!(target_os = "windows") {
+            assert!(shared.contains("    IMPORTED_LOCATION \"${_IMPORT_PREFIX}/bin/util.dll\"\n"));
+            assert!(shared.contains("    IMPORTED_IMPLIB \"${_IMPORT_PREFIX}/lib/util.dll.lib\"\n"));
+        } else {
+            assert!(shared.contains("    IMPORTED_LOCATION \"${_IMPORT_PREFIX}/lib/util.so\"\n"));
+        }
+
+        // A DLL's import library stays clear of a static library with the same name.
+        use toolchain::Toolchain;
+        assert_eq!(
+            toolchain::Msvc.link_out(std::path::Path::new("out/util.dll")),
+            ["/OUT:out/util.dll", "/IMPLIB:out/util.dll.lib"]
+        );
+        assert_eq!(
+            toolchain::Msvc.link_out(std::path::Path::new("out/util.lib")),
+            ["/OUT:out/util.lib"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn embed_regeneration() {
+        let dir = generator_project("embed-regen");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("a/icon.txt"), "a").unwrap();
+        std::fs::write(dir.join("b/icon.txt"), "b").unwrap();
+        let builder = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .file(CompilationFile::new("util.c"))
+            .include("include")
+            .embed("a/icon.txt");
+
+        let mut clash = builder.clone().embed("b/icon.txt").build();
+        assert!(matches!(clash.run(), compiler::Status::Aborted));
+        assert!(!dir.join("out/embed/icon_txt.c").exists());
+
+        let mut command = builder.embed_as("b/icon.txt", "b_icon_txt").build();
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .bin_type(linker::BinType::StaticLib)
+            .name("libutil")
+            .build();
+        let ninja = generator::NinjaGenerator::new()
+            .link(&link)
+            .regenerate(["./configure"], ["configure"])
+            .generate();
+        assert!(ninja.contains("build build.ninja: regenerate configure a/icon.txt b/icon.txt\n"));
+        let make = generator::MakefileGenerator::new(&link)
+            .regenerate(["./configure"], ["configure"])
+            .generate();
+        assert!(make.contains("Makefile: configure a/icon.txt b/icon.txt\n\t./configure\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn custom_steps() {
+        let dir = std::env::temp_dir().join(format!("cbuild-steps-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("value.txt"), "42").unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "int get(void);\nint main(void) { return get() == 42 ? 0 : 1; }\n",
+        )
+        .unwrap();
+        // Declared out of order: `get.c` needs the header written by the other step.
+        let source = step::CustomStep::new(
+            "source",
+            invocation::Invocation::new("sh")
+                .args(["-c", "printf '#include \"value.h\"\\nint get(void) { return VALUE; }\\n' > gen/get.c"])
+                .input("gen/value.h")
+                .output("gen/get.c"),
+        );
+        let header = step::CustomStep::new(
+            "header",
+            invocation::Invocation::new("sh")
+                .args([
+                    "-c",
+                    "echo \"#define VALUE $(cat value.txt)\" > gen/value.h",
+                ])
+                .input("value.txt")
+                .output("gen/value.h"),
+        );
+
+        let build = || {
+            let mut command = compiler::CompileCommand::builder()
+                .compiler(compiler::Compiler::Gcc)
+                .working_dir(&dir)
+                .out_dir("out")
+                .file(CompilationFile::new("main.c"))
+                .include("gen")
+                .step(source.clone())
+                .step(header.clone())
+                .build();
+            assert!(matches!(command.run(), compiler::Status::Success));
+            let mut link = linker::LinkerCommand::builder(&mut command)
+                .linker(linker::Linker::Gcc)
+                .name("app")
+                .build();
+            assert!(matches!(link.run(), compiler::Status::Success));
+        };
+        build();
+        let status = std::process::Command::new(dir.join("out/app")).status();
+        assert!(status.unwrap().success());
+
+        let generated = std::fs::metadata(dir.join("gen/get.c"))
+            .and_then(|meta| meta.modified())
+            .unwrap();
+        build();
+        assert_eq!(
+            std::fs::metadata(dir.join("gen/get.c"))
+                .and_then(|meta| meta.modified())
+                .unwrap(),
+            generated
+        );
+
+        let cyclic = compiler::CompileCommand::builder()
+            .working_dir(&dir)
+            .step(step::CustomStep::new(
+                "a",
+                invocation::Invocation::new("true").input("b").output("a"),
+            ))
+            .step(step::CustomStep::new(
+                "b",
+                invocation::Invocation::new("true").input("a").output("b"),
+            ))
+            .build()
+            .run();
+        assert!(matches!(cyclic, compiler::Status::Aborted));
+
+        // Generated assembly is compiled like generated C; headers are only included.
+        let generated = step::CustomStep::new(
+            "generated",
+            ["gen/a.s", "gen/b.S", "gen/c.asm", "gen/d.h", "gen/e.cpp"]
+                .iter()
+                .fold(invocation::Invocation::new("true"), |step, out| {
+                    step.output(out)
+                }),
+        );
+        assert_eq!(
+            generated.sources().collect::<Vec<_>>(),
+            ["gen/a.s", "gen/b.S", "gen/c.asm", "gen/e.cpp"]
+                .map(PathBuf::from)
+                .iter()
+                .collect::<Vec<_>>()
+        );
+
+        // A generator that isn't installed fails the build instead of leaving stale output.
+        let missing = compiler::CompileCommand::builder()
+            .working_dir(&dir)
+            .step(step::CustomStep::new(
+                "missing",
+                invocation::Invocation::new("no-such-generator")
+                    .input("value.txt")
+                    .output("gen/missing.h"),
+            ))
+            .build()
+            .run();
+        assert!(matches!(missing, compiler::Status::Aborted));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn assembly_sources() {
+        let dir = std::env::temp_dir().join(format!("cbuild-asm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("answer.S"),
+            ".globl answer\nanswer:\n    mov $ANSWER, %eax\n    ret\n.section .note.GNU-stack,\"\",@progbits\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("one.s"),
+            ".globl one\none:\n    mov $1, %eax\n    ret\n.section .note.GNU-stack,\"\",@progbits\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "int answer(void);\nint one(void);\n\
+             int main(void) { return answer() == 42 && one() == 1 ? 0 : 1; }\n",
+        )
+        .unwrap();
+
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .std(compiler::Std::C11)
+            .definition("ANSWER=42".into())
+            .include("inc")
+            .file(CompilationFile::new("main.c"))
+            .file(CompilationFile::new("answer.S"))
+            .file(CompilationFile::new("one.s"))
+            .build();
+        let plain = command.raw_command_for_file(&CompilationFile::new("one.s"));
+        assert!(!plain.to_string().contains("-std"));
+        assert!(!plain.to_string().contains("ANSWER"));
+        let nasm =
+            command.raw_command_for_file(&CompilationFile::from_parts("x.asm", "out/obj/x.o"));
+        assert_eq!(
+            nasm.to_string(),
+            "nasm -f elf64 -o out/obj/x.o x.asm -Iinc/ -DANSWER=42"
+        );
+
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let mut link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        assert!(matches!(link.run(), compiler::Status::Success));
+        let status = std::process::Command::new(dir.join("out/app")).status();
+        assert!(status.unwrap().success());
+
+        // The object format follows the detected target, not the host.
+        let mingw = compiler::CompileCommand::builder()
+            .toolchain_info(toolchain::ToolchainInfo::new(
+                "x86_64-w64-mingw32-gcc",
+                toolchain::Family::Gcc,
+                toolchain::Version::new(12, 0, 0),
+                "x86_64-w64-mingw32",
+            ))
+            .build();
+        let nasm = mingw.raw_command_for_file(&CompilationFile::from_parts("x.asm", "x.o"));
+        assert_eq!(nasm.to_string(), "nasm -f win64 -o x.o x.asm");
+        for (target, format) in [
+            ("i686-linux-gnu", "elf32"),
+            ("x86_64-apple-darwin", "macho64"),
+            ("i686-pc-windows-msvc", "win32"),
+        ] {
+            assert_eq!(compiler::Assembler::Nasm.format(target), format);
+        }
+
+        // cl.exe has no GAS front end.
+        let mut msvc = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Msvc)
+            .working_dir(&dir)
+            .out_dir("msvc")
+            .file(CompilationFile::new("one.s"))
+            .build();
+        assert!(matches!(msvc.run(), compiler::Status::Aborted));
+        assert!(!dir.join("msvc").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sanitizers() {
+        let dir = std::env::temp_dir().join(format!("cbuild-sanitize-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include <stdlib.h>\n\
+             int main(void) { int *p = malloc(sizeof(int)); p[1] = 0; free(p); return 0; }\n",
+        )
+        .unwrap();
+        let builder = || {
+            compiler::CompileCommand::builder()
+                .compiler(compiler::Compiler::Gcc)
+                .working_dir(&dir)
+                .out_dir("out")
+                .file(CompilationFile::new("main.c"))
+        };
+
+        let mut command = builder()
+            .sanitizer(compiler::Sanitizer::Address)
+            .sanitizer(compiler::Sanitizer::Undefined)
+            .build();
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let mut link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        assert!(link
+            .build_command()
+            .get_args()
+            .contains(&"-fsanitize=address,undefined".to_string()));
+        assert!(matches!(link.run(), compiler::Status::Success));
+        let out = std::process::Command::new(dir.join("out/app"))
+            .output()
+            .unwrap();
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("heap-buffer-overflow"));
+
+        let mut conflicting = builder()
+            .sanitizer(compiler::Sanitizer::Address)
+            .sanitizer(compiler::Sanitizer::Thread)
+            .build();
+        assert!(matches!(conflicting.run(), compiler::Status::Aborted));
+        let mut unsupported = builder().sanitizer(compiler::Sanitizer::Memory).build();
+        assert!(matches!(unsupported.run(), compiler::Status::Aborted));
+        let mut bare = builder().sanitizer(compiler::Sanitizer::Thread).build();
+        let mut link = linker::LinkerCommand::builder(&mut bare)
+            .linker(linker::Linker::Ld)
+            .build();
+        assert!(matches!(link.run(), compiler::Status::Aborted));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn link_time_optimization() {
+        let dir = std::env::temp_dir().join(format!("cbuild-lto-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("answer.c"), "int answer(void) { return 42; }\n").unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "int answer(void);\nint main(void) { return answer() == 42 ? 0 : 1; }\n",
+        )
+        .unwrap();
+        let build = |lto: Option<compiler::Lto>| {
+            let mut builder = compiler::CompileCommand::builder()
+                .compiler(compiler::Compiler::Gcc)
+                .working_dir(&dir)
+                .out_dir("out")
+                .opt_level(compiler::OptimizationLevel::O2)
+                .file(CompilationFile::new("answer.c"))
+                .file(CompilationFile::new("main.c"));
+            if let Some(lto) = lto {
+                builder = builder.lto(lto);
+            }
+            let mut command = builder.build();
+            assert!(matches!(command.run(), compiler::Status::Success));
+            let mut link = linker::LinkerCommand::builder(&mut command)
+                .linker(linker::Linker::Gcc)
+                .name("app")
+                .build();
+            assert!(
+                link.build_command()
+                    .get_args()
+                    .contains(&"-flto".to_string())
+                    == lto.is_some()
+            );
+            assert!(matches!(link.run(), compiler::Status::Success));
+            let archive = linker::LinkerCommand::builder(&mut command)
+                .linker(linker::Linker::Gcc)
+                .bin_type(linker::BinType::StaticLib)
+                .name("libanswer")
+                .build();
+            let archiver = archive.build_command().get_program().to_string();
+            assert_eq!(archiver == "gcc-ar", lto.is_some());
+        };
+        let modified = || {
+            std::fs::metadata(dir.join("out/obj/answer.o"))
+                .and_then(|meta| meta.modified())
+                .unwrap()
+        };
+
+        build(Some(compiler::Lto::Full));
+        let status = std::process::Command::new(dir.join("out/app")).status();
+        assert!(status.unwrap().success());
+        let built = modified();
+        build(Some(compiler::Lto::Full));
+        assert_eq!(modified(), built);
+        build(None);
+        assert_ne!(modified(), built);
+
+        let mut bare = compiler::CompileCommand::builder()
+            .working_dir(&dir)
+            .lto(compiler::Lto::Thin)
+            .build();
+        let mut link = linker::LinkerCommand::builder(&mut bare)
+            .linker(linker::Linker::Ld)
+            .build();
+        assert!(matches!(link.run(), compiler::Status::Aborted));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn coverage_report() {
+        let dir = std::env::temp_dir().join(format!("cbuild-coverage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("sign.c"),
+            "int sign(int x) {\n    if (x < 0)\n        return -1;\n    return 1;\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "int sign(int x);\nint main(void) { return sign(1) == 1 ? 0 : 1; }\n",
+        )
+        .unwrap();
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .coverage(true)
+            .file(CompilationFile::new("sign.c"))
+            .file(CompilationFile::new("main.c"))
+            .build();
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let mut link = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .build();
+        assert!(matches!(link.run(), compiler::Status::Success));
+
+        let runs = [invocation::Invocation::new(dir.join("out/app").display()).cwd(&dir)];
+        // Counters are reset between collections, so both see a single run.
+        for _ in 0..2 {
+            let report = coverage::CoverageReport::collect(&command, &runs).unwrap();
+            let sign = &report.files()[1];
+            assert_eq!(sign.path(), std::path::Path::new("sign.c"));
+            assert_eq!((sign.lines_hit(), sign.lines_total()), (3, 4));
+            assert_eq!((sign.branches_hit(), sign.branches_total()), (1, 2));
+        }
+        let info = std::fs::read_to_string(dir.join("out/coverage/coverage.info")).unwrap();
+        assert!(info.contains("SF:sign.c\n") && info.contains("DA:3,0\n"));
+        let summary = std::fs::read_to_string(dir.join("out/coverage/summary.txt")).unwrap();
+        assert!(summary
+            .lines()
+            .any(|line| line.split_whitespace().eq(["sign.c", "75.0%", "50.0%"])));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn profile_guided_optimization() {
+        let dir = std::env::temp_dir().join(format!("cbuild-pgo-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include <stdlib.h>\n\
+             static int step(int x) { return x % 3 ? x * 2 : x + 1; }\n\
+             int main(int argc, char **argv) {\n\
+                 int n = argc > 1 ? atoi(argv[1]) : 0, sum = 0;\n\
+                 for (int i = 0; i < n; i++) sum += step(i);\n\
+                 return sum < 0;\n\
+             }\n",
+        )
+        .unwrap();
+        let builder = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .opt_level(compiler::OptimizationLevel::O2)
+            .file(CompilationFile::new("main.c"));
+        let pgo = pgo::Pgo::new(builder)
+            .link(|link| link.linker(linker::Linker::Gcc).name("app"))
+            .training(|app| invocation::Invocation::new(app.display()).arg("1000"));
+        assert!(matches!(pgo.run(), compiler::Status::Success));
+        assert!(dir.join("out/pgo/profile/main.gcda").exists());
+        assert!(!dir.join("out/pgo/generate/obj/main.gcda").exists());
+        let status = std::process::Command::new(dir.join("out/pgo/use/app"))
+            .arg("10")
+            .status();
+        assert!(status.unwrap().success());
+
+        let optimized = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out/pgo/use")
+            .pgo(pgo::PgoPhase::Use(pgo.profile_dir()))
+            .file(CompilationFile::new("main.c"))
+            .build();
+        let args = optimized.flags();
+        let absolute = dir.canonicalize().unwrap();
+        assert!(args.contains(&format!(
+            "-fprofile-use={}",
+            absolute.join("out/pgo/profile").display()
+        )));
+        assert!(args.contains(&format!(
+            "-fprofile-prefix-path={}",
+            absolute.join("out/pgo/use/obj").display()
+        )));
+
+        // New training data must not restore objects optimized with the old.
+        let key = || optimized.cache_key(&optimized.units()[0], "gcc").unwrap();
+        let before = key();
+        let profile = dir.join("out/pgo/profile/main.gcda");
+        let mut data = std::fs::read(&profile).unwrap();
+        data.push(0);
+        std::fs::write(&profile, data).unwrap();
+        assert_ne!(key(), before);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_suite() {
+        let dir = std::env::temp_dir().join(format!("cbuild-tests-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tests/slow")).unwrap();
+        std::fs::write(dir.join("answer.c"), "int answer(void) { return 42; }\n").unwrap();
+        std::fs::write(dir.join("tests/common.h"), "int answer(void);\n").unwrap();
+        std::fs::write(
+            dir.join("tests/pass.c"),
+            "#include \"common.h\"\nint main(void) { return answer() != 42; }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("tests/fail.c"),
+            "#include <stdio.h>\n#include \"common.h\"\n\
+             int main(void) { printf(\"got %d <\\n\", answer()); return 1; }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("tests/slow/sleep.c"),
+            "#include <unistd.h>\nint main(void) { fork(); sleep(10); return 0; }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("tests/slow_sleep.c"),
+            "int main(void) { return 0; }\n",
+        )
+        .unwrap();
+        let builder = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out");
+        let mut command = builder
+            .clone()
+            .file(CompilationFile::new("answer.c"))
+            .build();
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let mut library = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .bin_type(linker::BinType::StaticLib)
+            .name("libanswer")
+            .build();
+        assert!(matches!(library.run(), compiler::Status::Success));
+
+        let suite = testing::TestSuite::new(builder.clone())
+            .dir("tests")
+            .library(&library)
+            .link(|link| link.linker(linker::Linker::Gcc))
+            .timeout(Duration::from_millis(500));
+        let report = suite.run();
+        let outcomes = report
+            .results()
+            .iter()
+            .map(|result| (result.name(), result.outcome().clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [
+                ("fail", testing::Outcome::Failed("exit status: 1".into())),
+                ("pass", testing::Outcome::Passed),
+                ("slow_sleep", testing::Outcome::TimedOut),
+                (
+                    "slow_sleep",
+                    testing::Outcome::Failed(
+                        "test name `slow_sleep` is taken by `tests/slow/sleep.c`".into()
+                    )
+                ),
+            ]
+        );
+        // The forked child keeps the output pipes open, but isn't waited for.
+        assert!(report.results()[2].duration() < Duration::from_secs(5));
+        assert_eq!(report.results()[0].stdout(), "got 42 <\n");
+        assert!(matches!(report.status(), compiler::Status::Aborted));
+        let junit = std::fs::read_to_string(dir.join("out/tests/junit.xml")).unwrap();
+        assert!(junit.contains("tests=\"4\" failures=\"3\" errors=\"0\""));
+        assert!(junit.contains("<system-out>got 42 &lt;\n</system-out>"));
+
+        // Names are relative to everything before the pattern's first wildcard.
+        std::fs::create_dir_all(dir.join("checks/unit")).unwrap();
+        std::fs::write(
+            dir.join("checks/unit/ok.c"),
+            "int main(void) { return 0; }\n",
+        )
+        .unwrap();
+        for suite in [
+            testing::TestSuite::new(builder.clone()).dir("checks/unit"),
+            testing::TestSuite::new(builder).pattern("checks/unit/ok.c"),
+        ] {
+            let report = suite.link(|link| link.linker(linker::Linker::Gcc)).run();
+            assert_eq!(report.results().len(), 1);
+            assert_eq!(report.results()[0].name(), "ok");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn run_executable() {
+        let dir = std::env::temp_dir().join(format!("cbuild-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("greet.c"), "int greet(int n) { return n * 2; }\n").unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include <stdlib.h>\nint greet(int);\n\
+             int main(int argc, char **argv) {\n\
+                 return greet(argc) + atoi(getenv(\"BASE\"));\n\
+             }\n",
+        )
+        .unwrap();
+        let mut shared = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out/greet")
+            .pic(true)
+            .file(CompilationFile::new("greet.c"))
+            .build();
+        assert!(matches!(shared.run(), compiler::Status::Success));
+        let mut library = linker::LinkerCommand::builder(&mut shared)
+            .linker(linker::Linker::Gcc)
+            .bin_type(linker::BinType::DynamicLib)
+            .name("libgreet")
+            .build();
+        assert!(matches!(library.run(), compiler::Status::Success));
+
+        let mut command = compiler::CompileCommand::builder()
+            .compiler(compiler::Compiler::Gcc)
+            .working_dir(&dir)
+            .out_dir("out")
+            .file(CompilationFile::new("main.c"))
+            .build();
+        assert!(matches!(command.run(), compiler::Status::Success));
+        let mut app = linker::LinkerCommand::builder(&mut command)
+            .linker(linker::Linker::Gcc)
+            .name("app")
+            .links(["greet"])
+            .link_dirs(["out/greet"])
+            .build();
+        assert!(matches!(app.run(), compiler::Status::Success));
+        assert_eq!(app.out_file(), dir.join("out/app"));
+        let linked = app.out_file().metadata().unwrap().modified().unwrap();
+
+        // Args are forwarded, the wrapper sets the environment, the exit code comes back.
+        let code = app.execute(|run| run.args(["a", "b"]).wrap("env", ["BASE=1"]));
+        assert_eq!(code, Some(7));
+        let modified = app.out_file().metadata().unwrap().modified().unwrap();
+        assert_eq!(linked, modified);
+
+        // An edited source is recompiled and relinked before running.
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(
+            dir.join("main.c"),
+            "int greet(int);\nint main(int argc, char **argv) { return greet(argc) + 10; }\n",
+        )
+        .unwrap();
+        assert_eq!(app.execute(|run| run), Some(12));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cli_run() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(cli::Run::parse(args(&[])).is_err());
+        assert!(cli::Run::parse(args(&["main.c", "--env", "BASE"])).is_err());
+        assert!(cli::Run::parse(args(&["main.c", "--no-such-option"])).is_err());
+        let run = cli::Run::parse(args(&["main.c", "--", "-o", "x"])).unwrap();
+        assert_eq!(run.args(), ["-o", "x"]);
+
+        let dir = std::env::temp_dir().join(format!("cbuild-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("main.c"),
+            "#include <stdlib.h>\n\
+             int main(int argc, char **argv) { return argc + EXTRA + atoi(getenv(\"BASE\")); }\n",
+        )
+        .unwrap();
+        let dir_arg = dir.display().to_string();
+        let run = |extra: &[&str]| {
+            let mut cmd = args(&["run", "-C", &dir_arg, "-DEXTRA=3", "--env", "BASE=4"]);
+            cmd.extend(args(extra));
+            cmd.extend(args(&["main.c", "--", "x"]));
+            cli::main(cmd)
+        };
+        assert_eq!(run(&[]), 9);
+        assert_eq!(run(&["--wrapper", "env BASE=5"]), 10);
+        assert!(dir.join("out/app").exists());
+        assert_eq!(cli::main(args(&["build"])), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn build_script() {
+        let dir = std::env::temp_dir().join(format!("cbuild-script-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("include")).unwrap();
+        std::fs::write(dir.join("include/answer.h"), "#define ANSWER 42\n").unwrap();
+        std::fs::write(
+            dir.join("answer.c"),
+            "#include \"answer.h\"\nint answer(void) { return ANSWER; }\n",
+        )
+        .unwrap();
+        let vars = HashMap::from([
+            ("OUT_DIR", dir.join("out").display().to_string()),
+            ("CARGO_MANIFEST_DIR", dir.display().to_string()),
+            ("TARGET", "x86_64-unknown-linux-gnu".to_string()),
+            ("OPT_LEVEL", "2".to_string()),
+            ("DEBUG", "true".to_string()),
+            ("CC", "gcc -m64".to_string()),
+            ("AR_x86_64-unknown-linux-gnu", "gcc-ar".to_string()),
+            ("CFLAGS", "-Wall".to_string()),
+            ("CFLAGS_x86_64_unknown_linux_gnu", "-Wextra".to_string()),
+        ]);
+        let script = cargo::BuildScript::from_vars(|key| vars.get(key).cloned()).unwrap();
+        let mut command = script
+            .configure(compiler::CompileCommand::builder())
+            .file(CompilationFile::new("answer.c"))
+            .include("include")
+            .build();
+        let file = &command.files()[0];
+        assert_eq!(command.compiler(), "gcc");
+        assert_eq!(
+            command.raw_command_for_file(file).get_args()[4..],
+            ["-O2", "-g", "-fPIC", "-I", "include", "-m64", "-Wall", "-Wextra"]
+        );
+        // What `run` executes, depfile included.
+        let depfile = format!("{}.d", file.out().display());
+        assert!(command.build_command_for_file(file).get_args().ends_with(&[
+            "-MMD".to_string(),
+            "-MF".to_string(),
+            depfile
+        ]));
+        assert!(matches!(
+            script.compile("answer", &mut command),
+            compiler::Status::Success
+        ));
+        assert!(dir.join("out/libanswer.a").exists());
+        let env = script.env_directives();
+        for name in ["CC_x86_64_unknown_linux_gnu", "TARGET_CFLAGS", "AR"] {
+            assert!(env.contains(&format!("cargo:rerun-if-env-changed={}", name)));
+        }
+        assert_eq!(
+            cargo::BuildScript::rerun_directives(&command),
+            [
+                format!("cargo:rerun-if-changed={}", dir.join("answer.c").display()),
+                format!(
+                    "cargo:rerun-if-changed={}",
+                    dir.join("include/answer.h").display()
+                ),
+            ]
+        );
+
+        // A compiler cache in `CC` becomes the launcher; `AR` does the archiving.
+        let mut vars = vars;
+        vars.insert("CC", "sccache gcc".to_string());
+        vars.insert("AR", "no-such-ar".to_string());
+        vars.remove("AR_x86_64-unknown-linux-gnu");
+        let script = cargo::BuildScript::from_vars(|key| vars.get(key).cloned()).unwrap();
+        let command = script
+            .configure(compiler::CompileCommand::builder())
+            .file(CompilationFile::new("answer.c"))
+            .build();
+        let invocation = command.build_command_for_file(&command.files()[0]);
+        assert_eq!(invocation.get_program(), "sccache");
+        assert_eq!(invocation.get_args()[0], "gcc");
+        vars.insert("CC", "gcc".to_string());
+        let script = cargo::BuildScript::from_vars(|key| vars.get(key).cloned()).unwrap();
+        let mut command = script
+            .configure(compiler::CompileCommand::builder())
+            .file(CompilationFile::new("answer.c"))
+            .include("include")
+            .build();
+        assert!(matches!(
+            script.compile("answer", &mut command),
+            compiler::Status::Aborted
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,8 +1,12 @@
 use crate::compiler::*;
+use crate::hash::Hasher;
+use crate::invocation::Invocation;
 use crate::path::Normalize;
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use tracing::{event, span, Level};
+use crate::toolchain::{self, Toolchain};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use tracing::{event, Level};
 
 #[derive(PartialEq)]
 pub enum BinType {
@@ -22,24 +26,40 @@ pub enum Linker {
 }
 
 impl Linker {
-    const fn sys_default() -> Self {
-        #[cfg(target_os = "windows")]
-        return Self::Link;
-        #[cfg(target_os = "macos")]
-        return Self::LLVM_LD;
-        #[cfg(target_os = "linux")]
-        return Self::Ld;
-    }
-
-    /*fn as_str(&self) -> &str {
+    pub fn toolchain(&self) -> Arc<dyn Toolchain> {
         match self {
-            Linker::Clang => return "clang",
-            Linker::LLVM_LD => return "lld",
-            Linker::Gcc => return "gcc",
-            Linker::Ld => return "ld",
-            Linker::Link => return "link",
+            Linker::Clang => return Arc::new(toolchain::Clang),
+            Linker::LLVM_LD => {
+                return Arc::new(toolchain::Ld::new("lld").driver(&toolchain::Clang))
+            }
+            Linker::Gcc => return Arc::new(toolchain::Gcc),
+            Linker::Ld => return Arc::new(toolchain::Ld::new("ld")),
+            Linker::Link => return Arc::new(toolchain::Msvc),
         }
-    }*/
+    }
+}
+
+// The variable the dynamic loader searches for shared libraries.
+pub(crate) const fn library_path_var() -> &'static str {
+    #[cfg(target_os = "windows")]
+    return "PATH";
+    #[cfg(target_os = "macos")]
+    return "DYLD_LIBRARY_PATH";
+    #[cfg(target_os = "linux")]
+    return "LD_LIBRARY_PATH";
+}
+
+// `dirs` ahead of the loader's current search path.
+pub(crate) fn library_path(dirs: impl IntoIterator<Item = PathBuf>) -> String {
+    let current = std::env::var_os(library_path_var()).unwrap_or_default();
+    let paths = dirs
+        .into_iter()
+        .chain(std::env::split_paths(&current))
+        .collect::<Vec<_>>();
+    return std::env::join_paths(paths)
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
 }
 
 pub struct LinkerCommandBuilder<'a> {
@@ -56,10 +76,12 @@ impl<'a> LinkerCommandBuilder<'a> {
             inner: LinkerCommand {
                 compile_command: command,
                 bin_type: BinType::Binary,
-                linker: Linker::sys_default(),
+                toolchain: None,
+                archiver: None,
                 name: default_exec_name().to_string(),
                 links: Vec::new(),
                 link_dirs: Vec::new(),
+                link_files: Vec::new(),
                 flags: Vec::new(),
                 link_sys_deafult: false,
             },
@@ -86,7 +108,18 @@ impl<'a> LinkerCommandBuilder<'a> {
     }
 
     pub fn linker(mut self, linker: Linker) -> Self {
-        self.inner.linker = linker;
+        self.inner.toolchain = Some(linker.toolchain());
+        return self;
+    }
+
+    pub fn toolchain(mut self, toolchain: impl Toolchain + 'static) -> Self {
+        self.inner.toolchain = Some(Arc::new(toolchain));
+        return self;
+    }
+
+    // Archives static libraries with `program` instead of the toolchain's archiver.
+    pub fn archiver(mut self, program: impl ToString) -> Self {
+        self.inner.archiver = Some(program.to_string());
         return self;
     }
 
@@ -131,6 +164,13 @@ impl<'a> LinkerCommandBuilder<'a> {
         return self;
     }
 
+    // An object or archive linked by path, right after the compiled objects.
+    // Ignored when building a static library.
+    pub fn link_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.inner.link_files.push(file.into().normalize());
+        return self;
+    }
+
     pub fn flag(mut self, flag: impl ToString) -> Self {
         self.inner.flags.push(flag.to_string());
         return self;
@@ -158,55 +198,58 @@ impl<'a> LinkerCommandBuilder<'a> {
 pub struct LinkerCommand<'a> {
     compile_command: &'a mut CompileCommand,
     bin_type: BinType,
-    linker: Linker,
+    // `None` links through the compile command's driver, which finds the runtimes
+    // that sanitizers, LTO, coverage and PGO need.
+    toolchain: Option<Arc<dyn Toolchain>>,
+    archiver: Option<String>,
     name: String,
     links: Vec<PathBuf>,
     link_dirs: Vec<PathBuf>,
+    link_files: Vec<PathBuf>,
     flags: Vec<String>,
     link_sys_deafult: bool,
 }
 
 impl<'a> LinkerCommand<'a> {
-    pub fn builder(command: &'a mut CompileCommand) -> LinkerCommandBuilder {
+    pub fn builder(command: &'a mut CompileCommand) -> LinkerCommandBuilder<'a> {
         return LinkerCommandBuilder::new(command);
     }
 
-    const fn get_sys_deafault_libs() -> &'static [&'static str] {
-        return &[];
+    pub fn compile_command(&self) -> &CompileCommand {
+        self.compile_command
     }
 
-    fn to_out(&mut self) -> Vec<String> {
-        let mut name = PathBuf::from(&self.name);
-        name.set_extension(self.get_exec_ext());
-        let out = self.compile_command.out_dir().join(name);
-        match self.linker {
-            Linker::Clang | Linker::Gcc | Linker::Ld | Linker::LLVM_LD => {
-                return Vec::from(["-o".into(), out.display().to_string()])
-            }
-            Linker::Link => return Vec::from([format!("/OUT:{}", out.display().to_string())]),
-        }
+    pub fn bin_type(&self) -> &BinType {
+        &self.bin_type
     }
 
-    pub fn out_file(&mut self) -> PathBuf {
-        self.compile_command.out_dir().join(&self.name)
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    fn link_file(&self, file: &PathBuf) -> Vec<String> {
-        match self.linker {
-            Linker::Clang | Linker::Ld | Linker::Gcc | Linker::LLVM_LD => {
-                return Vec::from(["-l".into(), file.display().to_string()])
-            }
-            Linker::Link => return Vec::from([file.display().to_string()]),
-        }
+    pub fn links(&self) -> &[PathBuf] {
+        &self.links
     }
 
-    fn link_dir(&self, dir: &PathBuf) -> Vec<String> {
-        match self.linker {
-            Linker::Clang | Linker::Ld | Linker::Gcc | Linker::LLVM_LD => {
-                return Vec::from(["-L".into(), dir.display().to_string()])
-            }
-            Linker::Link => return Vec::from([format!("/LIBPATH:{}", dir.display().to_string())]),
-        }
+    pub fn link_dirs(&self) -> &[PathBuf] {
+        &self.link_dirs
+    }
+
+    pub fn link_files(&self) -> &[PathBuf] {
+        &self.link_files
+    }
+
+    pub(crate) fn artifact_path(&self) -> PathBuf {
+        let mut name = PathBuf::from(&self.name);
+        name.set_extension(self.get_exec_ext());
+        return self.compile_command.out_dir().join(name);
+    }
+
+    // The artifact, extension included, as seen from the current directory.
+    // Breaking change: this used to take `&mut self` and return `out_dir/<name>`
+    // without the extension, relative to the working directory.
+    pub fn out_file(&self) -> PathBuf {
+        return self.compile_command.resolve(&self.artifact_path());
     }
 
     const fn get_exec_ext(&self) -> &'static str {
@@ -242,8 +285,14 @@ impl<'a> LinkerCommand<'a> {
         } else {
             return true;
         };
-        for file in self.compile_command.get_link_files() {
-            if let Ok(metadata) = file.metadata() {
+        let extra = self.link_files.iter();
+        for file in self
+            .compile_command
+            .get_link_files()
+            .into_iter()
+            .chain(extra)
+        {
+            if let Ok(metadata) = self.compile_command.resolve(file).metadata() {
                 if let Ok(out_time) = metadata.modified() {
                     if time < out_time {
                         return true;
@@ -258,80 +307,227 @@ impl<'a> LinkerCommand<'a> {
         false
     }
 
-    /*
-       Linker::Clang => return "clang",
-       Linker::LLVM_LD => return "lld",
-       Linker::Gcc => return "gcc",
-       Linker::Ld => return "ld",
-       Linker::Link => return "link",
-    */
-
-    fn linker(&self) -> &'static str {
-        match (&self.linker, &self.bin_type) {
-            (Linker::Link, BinType::Binary) | (Linker::Link, BinType::DynamicLib) => "link.exe",
-            (Linker::Link, BinType::StaticLib) => "lib.exe",
-            (Linker::Clang, BinType::Binary) | (Linker::Clang, BinType::DynamicLib) => "clang",
-            (Linker::Clang, BinType::StaticLib) => "llvm-ar",
-            (Linker::LLVM_LD, _) => "lld",
-            (Linker::Ld, _) => "ld",
-            _ => todo!(),
+    fn linker(&self) -> &str {
+        if let (BinType::StaticLib, Some(archiver)) = (&self.bin_type, &self.archiver) {
+            return archiver;
+        }
+        match self.bin_type {
+            BinType::StaticLib if self.compile_command.lto_mode().is_some() => {
+                self.toolchain().lto_archiver()
+            }
+            BinType::StaticLib => self.toolchain().archiver(),
+            BinType::Binary | BinType::DynamicLib => match &self.toolchain {
+                Some(toolchain) => toolchain.linker(&self.bin_type),
+                None => self.compile_command.link_driver(&self.bin_type),
+            },
         }
     }
 
-    fn shared_flag(&self) -> &'static str {
-        match &self.linker {
-            Linker::Link => "/DLL",
-            Linker::Clang => "-shared",
-            _ => todo!(),
+    fn toolchain(&self) -> &dyn Toolchain {
+        match &self.toolchain {
+            Some(toolchain) => toolchain.as_ref(),
+            None => self.compile_command.toolchain(),
         }
     }
 
-    fn build_command(&mut self) -> Command {
-        let mut cmd = Command::new(self.linker());
-        cmd.current_dir(self.compile_command.working_dir());
-        if self.bin_type == BinType::DynamicLib {
-            cmd.arg(self.shared_flag());
+    pub fn build_command(&self) -> Invocation {
+        let out = self.artifact_path();
+        let link_files = self
+            .compile_command
+            .get_link_files()
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        let cmd = Invocation::new(self.linker())
+            .cwd(self.compile_command.working_dir())
+            .inputs(link_files.iter())
+            .output(&out);
+        if self.bin_type == BinType::StaticLib {
+            return cmd.args(self.toolchain().archive(&out, &link_files));
         }
 
-        if self.linker == Linker::Link {
-            cmd.arg("/nologo");
-        }
-        for part in self.to_out() {
-            cmd.arg(part);
-        }
+        let mut cmd = cmd
+            .args(self.toolchain().link_preamble(&self.bin_type))
+            .args(self.toolchain().link_out(&out));
         for dir in &self.link_dirs {
-            for part in self.link_dir(dir) {
-                cmd.arg(part);
-            }
+            cmd = cmd.args(self.toolchain().link_dir(dir));
+        }
+        for file in &link_files {
+            cmd = cmd.arg(file.display());
         }
-        for file in self.compile_command.get_link_files() {
-            cmd.arg(file);
+        for file in &self.link_files {
+            cmd = cmd.input(file).arg(file.display());
         }
         for link in &self.links {
-            for part in self.link_file(link) {
-                cmd.arg(part);
+            cmd = cmd.args(self.toolchain().link_lib(link));
+        }
+        cmd.args(self.compile_command.link_flags(self.toolchain()))
+            .args(&self.flags)
+    }
+
+    // Launches the binary from the working directory, with the library dirs of the
+    // build ahead of the loader's search path so shared libraries built here resolve.
+    pub fn run_invocation(&self) -> Invocation {
+        let dirs = std::iter::once(self.compile_command.out_dir().as_path())
+            .chain(self.link_dirs.iter().map(PathBuf::as_path))
+            .chain(self.link_files.iter().filter_map(|file| file.parent()))
+            .filter_map(|dir| std::path::absolute(self.compile_command.resolve(dir)).ok())
+            .fold(Vec::new(), |mut dirs, dir| {
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+                dirs
+            });
+        let program = std::path::absolute(self.out_file()).unwrap_or_else(|_| self.out_file());
+        return Invocation::new(program.display())
+            .cwd(self.compile_command.working_dir())
+            .env(library_path_var(), library_path(dirs));
+    }
+
+    // Compiles and links if needed, then runs the binary through `configure`, which can add
+    // arguments, environment or a wrapper like `valgrind`. Returns its exit code;
+    // a signal `n` is reported as `128 + n`, the way shells do. `cbuild run` drives this.
+    pub fn execute(&mut self, configure: impl FnOnce(Invocation) -> Invocation) -> Option<i32> {
+        if self.bin_type != BinType::Binary {
+            println!("[ERROR]: `{}` is a library, not an executable", self.name);
+            return None;
+        }
+        if let Status::Aborted = self.compile_command.run() {
+            return None;
+        }
+        if let Status::Aborted = self.run() {
+            return None;
+        }
+        let invocation = configure(self.run_invocation());
+        println!("[Running]: {}", invocation);
+        event!(Level::DEBUG, "executing: {}", invocation);
+        let status = match invocation.to_command().status() {
+            Ok(status) => status,
+            Err(e) => {
+                println!("[ERROR]: failed to run `{}`: `{}`", self.name, e);
+                return None;
+            }
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return Some(128 + signal);
             }
         }
-        for flag in &self.flags {
-            cmd.arg(flag);
+        return status.code();
+    }
+
+    // Identifies the linker binary, as the compile key does the compiler, so artifacts
+    // never cross linker versions or targets.
+    fn toolchain_fingerprint(&self, program: &str) -> String {
+        if program == self.compile_command.compiler() {
+            return self.compile_command.toolchain_fingerprint();
+        }
+        match toolchain::find_in_path(program).and_then(toolchain::ToolchainInfo::probe) {
+            Some(info) => info.fingerprint(),
+            None => program.to_string(),
         }
-        cmd
     }
 
-    pub fn run(&mut self) {
+    // Hash of the linker, its arguments with the output masked and every input's contents.
+    fn cache_key(&self, invocation: &Invocation) -> Option<String> {
+        let mut hasher = Hasher::new();
+        hasher.field(
+            self.toolchain_fingerprint(invocation.get_program())
+                .as_bytes(),
+        );
+        hasher.field(invocation.get_program().as_bytes());
+        let out = invocation.get_outputs().first()?.display().to_string();
+        for arg in invocation.get_args() {
+            hasher.field(arg.replace(&out, "<out>").as_bytes());
+        }
+        for input in invocation.get_inputs() {
+            hasher.field(&std::fs::read(self.compile_command.resolve(input)).ok()?);
+        }
+        return Some(hasher.finish());
+    }
+
+    pub fn run(&mut self) -> Status {
+        // Archives only bundle objects; the runtimes come in with the final link.
+        if self.bin_type != BinType::StaticLib {
+            let status = self
+                .compile_command
+                .check_sanitizers(self.toolchain(), |toolchain, sanitizers| {
+                    toolchain.sanitize_link(sanitizers)
+                });
+            if let Status::Aborted = status {
+                return Status::Aborted;
+            }
+            if let Some(lto) = self.compile_command.lto_mode() {
+                if self.toolchain().lto_link(&lto).is_none() {
+                    println!(
+                        "[ERROR]: `{:?}` LTO is not supported by {}",
+                        lto,
+                        self.toolchain().name()
+                    );
+                    return Status::Aborted;
+                }
+            }
+            if self.compile_command.pgo_phase().is_some() && self.toolchain().pgo().is_none() {
+                println!(
+                    "[ERROR]: profile-guided optimization is not supported by {}",
+                    self.toolchain().name()
+                );
+                return Status::Aborted;
+            }
+            if self.compile_command.coverage_format().is_some()
+                && self.toolchain().coverage().is_none()
+            {
+                println!(
+                    "[ERROR]: coverage is not supported by {}",
+                    self.toolchain().name()
+                );
+                return Status::Aborted;
+            }
+        }
         if !self.should_rerun() {
-            return;
+            return Status::Success;
+        }
+        let invocation = self.build_command();
+        let key = self
+            .compile_command
+            .has_cache()
+            .then(|| self.cache_key(&invocation))
+            .flatten();
+        let out = self.compile_command.resolve(&self.artifact_path());
+        if let Some(key) = &key {
+            let executable = self.bin_type != BinType::StaticLib;
+            if self.compile_command.restore_cached(key, &out, executable) {
+                println!("[Cached]: {}", self.name);
+                return Status::Success;
+            }
         }
-        let mut cmd = self.build_command();
         println!("[Linking]: {}", self.name);
-        event!(Level::DEBUG, "executing: {:?}", cmd);
+        event!(Level::DEBUG, "executing: {}", invocation);
+        let mut cmd = invocation.to_command();
         cmd.stdout(Stdio::inherit());
         cmd.stderr(Stdio::inherit());
         match cmd.output() {
-            Ok(_) => {}
+            Ok(out) => {
+                if !out.status.success() {
+                    println!("[ERROR]: failed to link `{}`", self.name);
+                    return Status::Aborted;
+                }
+            }
             Err(e) => {
-                event!(Level::WARN, "error occured: `{}`", e);
+                println!(
+                    "[ERROR]: failed to run `{}`: `{}`",
+                    invocation.get_program(),
+                    e
+                );
+                return Status::Aborted;
             }
         }
+        if let Some(key) = &key {
+            self.compile_command.store_cached(key, &out);
+            self.compile_command.evict_cached();
+        }
+        return Status::Success;
     }
 }
@@ -0,0 +1,3 @@
+fn main() {
+    std::process::exit(cbuild::cli::main(std::env::args().skip(1)));
+}
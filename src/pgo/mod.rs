@@ -0,0 +1,202 @@
+use crate::compiler::{CompileCommand, CompileCommandBuilder, Status};
+use crate::invocation::Invocation;
+use crate::linker::{LinkerCommand, LinkerCommandBuilder};
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+// How a toolchain records training profiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    // GCC's `.gcda` files, usable as they are.
+    Gcda,
+    // Clang's `.profraw` files, merged into `default.profdata` by `llvm-profdata`.
+    Llvm,
+}
+
+impl ProfileFormat {
+    // GCC names each profile after its object's path below `obj_dir`, so the
+    // instrumented and optimized builds agree on names despite separate out dirs.
+    pub fn generate_flags(&self, dir: &Path, obj_dir: &Path) -> Vec<String> {
+        let mut flags = Vec::from([format!("-fprofile-generate={}", dir.display())]);
+        if *self == ProfileFormat::Gcda {
+            flags.push(format!("-fprofile-prefix-path={}", obj_dir.display()));
+        }
+        flags
+    }
+
+    // Clang reads `dir/default.profdata` when given a directory.
+    pub fn use_flags(&self, dir: &Path, obj_dir: &Path) -> Vec<String> {
+        let mut flags = Vec::from([format!("-fprofile-use={}", dir.display())]);
+        if *self == ProfileFormat::Gcda {
+            flags.push(format!("-fprofile-prefix-path={}", obj_dir.display()));
+        }
+        flags
+    }
+
+    pub fn link_flags(&self) -> Vec<String> {
+        return Vec::from(["-fprofile-generate".into()]);
+    }
+}
+
+// Where a `CompileCommand` stands in a profile-guided build. Both phases name the
+// profile directory, relative to the working directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgoPhase {
+    // Instrumented objects that write profiles when run.
+    Generate(PathBuf),
+    // Objects optimized with the profiles; they rebuild when the profiles change.
+    Use(PathBuf),
+}
+
+impl PgoPhase {
+    pub fn dir(&self) -> &Path {
+        match self {
+            PgoPhase::Generate(dir) | PgoPhase::Use(dir) => return dir,
+        }
+    }
+}
+
+type Link = Box<dyn for<'a> Fn(LinkerCommandBuilder<'a>) -> LinkerCommandBuilder<'a>>;
+type Training = Box<dyn Fn(&Path) -> Invocation>;
+
+// Two-phase profile-guided optimization: an instrumented build in
+// `out_dir/pgo/generate`, training runs writing to `out_dir/pgo/profile`, then an
+// optimized build in `out_dir/pgo/use`. The phases never share objects.
+pub struct Pgo {
+    builder: CompileCommandBuilder,
+    link: Link,
+    training: Vec<Training>,
+}
+
+impl Pgo {
+    // Both phases are built from `builder`, with their own out dir and PGO flags.
+    pub fn new(builder: CompileCommandBuilder) -> Self {
+        return Self {
+            builder,
+            link: Box::new(|link| link),
+            training: Vec::new(),
+        };
+    }
+
+    // Configures the link of each phase, e.g. its name and libraries.
+    pub fn link(
+        mut self,
+        link: impl for<'a> Fn(LinkerCommandBuilder<'a>) -> LinkerCommandBuilder<'a> + 'static,
+    ) -> Self {
+        self.link = Box::new(link);
+        return self;
+    }
+
+    // A training run, given the path of the instrumented artifact.
+    pub fn training(mut self, run: impl Fn(&Path) -> Invocation + 'static) -> Self {
+        self.training.push(Box::new(run));
+        return self;
+    }
+
+    fn dir(&self, name: &str) -> PathBuf {
+        return self.builder.get_out_dir().join("pgo").join(name);
+    }
+
+    pub fn profile_dir(&self) -> PathBuf {
+        return self.dir("profile");
+    }
+
+    // Builds and links one phase, returning the artifact's path as seen from here.
+    fn build(&self, name: &str, phase: PgoPhase) -> Option<PathBuf> {
+        let mut command = self
+            .builder
+            .clone()
+            .out_dir(self.dir(name))
+            .pgo(phase)
+            .build();
+        if let Status::Aborted = command.run() {
+            return None;
+        }
+        let mut link = (self.link)(LinkerCommand::builder(&mut command)).build();
+        if let Status::Aborted = link.run() {
+            return None;
+        }
+        let artifact = link.artifact_path();
+        return Some(link.compile_command().resolve(&artifact));
+    }
+
+    // Profiles of earlier trainings would be merged into this one's.
+    fn reset_profiles(&self, dir: &Path) -> std::io::Result<()> {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        return std::fs::create_dir_all(dir);
+    }
+
+    fn merge(&self, command: &CompileCommand, dir: &Path) -> Status {
+        let profiles = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if profiles.is_empty() {
+            println!("[ERROR]: the training runs wrote no profiles");
+            return Status::Aborted;
+        }
+        if command.toolchain().pgo() != Some(ProfileFormat::Llvm) {
+            return Status::Success;
+        }
+        let merge = Invocation::new("llvm-profdata")
+            .args(["merge", "-o"])
+            .arg(dir.join("default.profdata").display())
+            .args(profiles.iter().map(|profile| profile.display()));
+        event!(Level::DEBUG, "executing: {}", merge);
+        match merge.to_command().status() {
+            Ok(status) if status.success() => return Status::Success,
+            Ok(_) => {}
+            Err(e) => event!(Level::WARN, "error occured: `{}`", e),
+        }
+        println!("[ERROR]: failed to merge the training profiles");
+        return Status::Aborted;
+    }
+
+    pub fn run(&self) -> Status {
+        let command = self.builder.clone().build();
+        let profiles = self.profile_dir();
+        let resolved = command.resolve(&profiles);
+        if let Err(e) = self.reset_profiles(&resolved) {
+            event!(Level::WARN, "failed to reset profile dir: `{}`", e);
+        }
+
+        println!("[PGO]: instrumented build");
+        let Some(instrumented) = self.build("generate", PgoPhase::Generate(profiles.clone()))
+        else {
+            return Status::Aborted;
+        };
+
+        println!("[PGO]: training");
+        for training in &self.training {
+            let run = training(&instrumented);
+            println!("[Running]: {}", run);
+            event!(Level::DEBUG, "executing: {}", run);
+            match run.to_command().status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => {
+                    println!("[ERROR]: training run exited with {}", status);
+                    return Status::Aborted;
+                }
+                Err(e) => {
+                    println!("[ERROR]: failed to run training: `{}`", e);
+                    return Status::Aborted;
+                }
+            }
+        }
+        if let Status::Aborted = self.merge(&command, &resolved) {
+            return Status::Aborted;
+        }
+
+        println!("[PGO]: optimized build");
+        match self.build("use", PgoPhase::Use(profiles)) {
+            Some(_) => return Status::Success,
+            None => return Status::Aborted,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::compiler::{is_outdated, SourceKind};
+use crate::invocation::Invocation;
+use std::path::{Path, PathBuf};
+
+// C and C++ extensions of generated files that are compiled rather than just included.
+// `SourceKind::of` counts everything else as C too, headers included.
+const C_EXTS: [&str; 6] = ["c", "cc", "cpp", "cxx", "c++", "C"];
+
+// A command run before compiling, such as flex, bison or a code generator. The
+// invocation's declared inputs and outputs drive both ordering and dirty checks;
+// paths are relative to the compile command's working directory.
+#[derive(Debug, Clone)]
+pub struct CustomStep {
+    name: String,
+    invocation: Invocation,
+}
+
+impl CustomStep {
+    pub fn new(name: impl ToString, invocation: Invocation) -> Self {
+        return Self {
+            name: name.to_string(),
+            invocation,
+        };
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn invocation(&self) -> &Invocation {
+        &self.invocation
+    }
+
+    pub fn inputs(&self) -> &[PathBuf] {
+        self.invocation.get_inputs()
+    }
+
+    pub fn outputs(&self) -> &[PathBuf] {
+        self.invocation.get_outputs()
+    }
+
+    // Outputs that join the compile command's file list.
+    pub fn sources(&self) -> impl Iterator<Item = &PathBuf> {
+        return self
+            .outputs()
+            .iter()
+            .filter(|output| match SourceKind::of(output) {
+                SourceKind::C => output
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| C_EXTS.contains(&ext)),
+                SourceKind::Assembly | SourceKind::PreprocessedAssembly | SourceKind::Nasm => true,
+            });
+    }
+
+    // Steps without outputs can't be checked and always run.
+    pub(crate) fn is_outdated(&self, resolve: impl Fn(&Path) -> PathBuf) -> bool {
+        if self.outputs().is_empty() {
+            return true;
+        }
+        let inputs = self
+            .inputs()
+            .iter()
+            .map(|input| resolve(input))
+            .collect::<Vec<_>>();
+        return self
+            .outputs()
+            .iter()
+            .any(|output| is_outdated(&resolve(output), &inputs));
+    }
+}
+
+// Orders `steps` so that each runs after the steps producing its inputs, keeping the
+// declaration order otherwise. `None` if the steps depend on each other in a cycle.
+pub(crate) fn schedule(steps: &[CustomStep]) -> Option<Vec<&CustomStep>> {
+    let producer = |input: &PathBuf| steps.iter().position(|step| step.outputs().contains(input));
+    let mut order = Vec::with_capacity(steps.len());
+    let mut done = vec![false; steps.len()];
+    while order.len() < steps.len() {
+        let ready = (0..steps.len()).find(|&i| {
+            !done[i]
+                && steps[i]
+                    .inputs()
+                    .iter()
+                    .filter_map(producer)
+                    .all(|dep| dep == i || done[dep])
+        })?;
+        done[ready] = true;
+        order.push(&steps[ready]);
+    }
+    return Some(order);
+}
@@ -0,0 +1,538 @@
+use crate::compiler::{CompilationFile, CompileCommandBuilder, Status};
+use crate::invocation::Invocation;
+use crate::linker::{BinType, LinkerCommand, LinkerCommandBuilder};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{event, Level};
+
+// Glob matching on `/`-separated paths: `*` and `?` stay within a component,
+// `**` spans any number of them.
+fn matches(pattern: &str, path: &str) -> bool {
+    if let Some(rest) = pattern.strip_prefix("**") {
+        let rest = rest.strip_prefix('/').unwrap_or(rest);
+        return (0..=path.len())
+            .filter(|&i| path.is_char_boundary(i))
+            .filter(|&i| i == 0 || path[..i].ends_with('/') || i == path.len())
+            .any(|i| matches(rest, &path[i..]));
+    }
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => return path.is_empty(),
+        Some('*') => {
+            let rest = chars.as_str();
+            return (0..=path.len())
+                .filter(|&i| path.is_char_boundary(i))
+                .take_while(|&i| !path[..i].contains('/'))
+                .any(|i| matches(rest, &path[i..]));
+        }
+        Some('?') => {
+            let mut path_chars = path.chars();
+            return match path_chars.next() {
+                Some(c) if c != '/' => matches(chars.as_str(), path_chars.as_str()),
+                _ => false,
+            };
+        }
+        Some(c) => {
+            return path
+                .strip_prefix(c)
+                .is_some_and(|rest| matches(chars.as_str(), rest))
+        }
+    }
+}
+
+// Headers and data files may sit next to the tests.
+fn is_source(path: &Path) -> bool {
+    let ext = path.extension().and_then(|ext| ext.to_str());
+    return matches!(ext, Some("c" | "cc" | "cpp" | "cxx"));
+}
+
+// The directories of `pattern` before its first wildcard: where discovery walks and
+// what test names are relative to. A pattern without wildcards names a file, so its
+// last component is never part of the prefix.
+fn literal_prefix(pattern: &str) -> String {
+    let parts = pattern.split('/').collect::<Vec<_>>();
+    let literal = parts
+        .iter()
+        .take_while(|part| !part.contains(['*', '?']))
+        .count()
+        .min(parts.len() - 1);
+    return parts[..literal].join("/");
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            walk(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Not representable in XML 1.0 at all.
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    // The exit status, e.g. `exit status: 1` or `signal: 11 (SIGSEGV)`, or why the
+    // test couldn't run.
+    Failed(String),
+    TimedOut,
+    // The test didn't compile or link; nothing ran.
+    BuildFailed,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    name: String,
+    src: PathBuf,
+    outcome: Outcome,
+    duration: Duration,
+    stdout: String,
+    stderr: String,
+}
+
+impl TestResult {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn src(&self) -> &Path {
+        &self.src
+    }
+
+    pub fn outcome(&self) -> &Outcome {
+        &self.outcome
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub fn stdout(&self) -> &str {
+        &self.stdout
+    }
+
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+
+    fn junit(&self) -> String {
+        let mut out = format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape_xml(&self.name),
+            escape_xml(&self.src.display().to_string()),
+            self.duration.as_secs_f64()
+        );
+        let problem = match &self.outcome {
+            Outcome::Passed => None,
+            Outcome::Failed(status) => {
+                Some(format!("<failure message=\"{}\"/>", escape_xml(status)))
+            }
+            Outcome::TimedOut => Some("<failure message=\"timed out\"/>".to_string()),
+            Outcome::BuildFailed => Some("<error message=\"build failed\"/>".to_string()),
+        };
+        if problem.is_none() && self.stdout.is_empty() && self.stderr.is_empty() {
+            out.push_str("/>\n");
+            return out;
+        }
+        out.push_str(">\n");
+        if let Some(problem) = problem {
+            out.push_str(&format!("      {}\n", problem));
+        }
+        if !self.stdout.is_empty() {
+            out.push_str(&format!(
+                "      <system-out>{}</system-out>\n",
+                escape_xml(&self.stdout)
+            ));
+        }
+        if !self.stderr.is_empty() {
+            out.push_str(&format!(
+                "      <system-err>{}</system-err>\n",
+                escape_xml(&self.stderr)
+            ));
+        }
+        out.push_str("    </testcase>\n");
+        out
+    }
+}
+
+pub struct TestReport {
+    results: Vec<TestResult>,
+    duration: Duration,
+}
+
+impl TestReport {
+    pub fn results(&self) -> &[TestResult] {
+        &self.results
+    }
+
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|result| result.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn status(&self) -> Status {
+        match self.failed() {
+            0 => return Status::Success,
+            _ => return Status::Aborted,
+        }
+    }
+
+    pub fn junit(&self) -> String {
+        let count = |outcome: fn(&Outcome) -> bool| {
+            self.results
+                .iter()
+                .filter(|result| outcome(&result.outcome))
+                .count()
+        };
+        let failures = count(|outcome| matches!(outcome, Outcome::Failed(_) | Outcome::TimedOut));
+        let errors = count(|outcome| *outcome == Outcome::BuildFailed);
+        let attributes = format!(
+            "tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\"",
+            self.results.len(),
+            failures,
+            errors,
+            self.duration.as_secs_f64()
+        );
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str(&format!("<testsuites {}>\n", attributes));
+        out.push_str(&format!("  <testsuite name=\"cbuild\" {}>\n", attributes));
+        for result in &self.results {
+            out.push_str(&result.junit());
+        }
+        out.push_str("  </testsuite>\n</testsuites>\n");
+        out
+    }
+}
+
+impl std::fmt::Display for TestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed in {:.2}s",
+            self.passed(),
+            self.failed(),
+            self.duration.as_secs_f64()
+        )
+    }
+}
+
+type Link = Box<dyn for<'a> Fn(LinkerCommandBuilder<'a>) -> LinkerCommandBuilder<'a>>;
+
+// C unit tests: every matching source is its own executable, linked against the
+// project's library and run in parallel. A test passes when it exits with 0.
+// Binaries go to `out_dir/tests`, along with a JUnit `junit.xml` of the last run.
+pub struct TestSuite {
+    builder: CompileCommandBuilder,
+    patterns: Vec<String>,
+    link: Link,
+    library: Option<PathBuf>,
+    library_links: Vec<PathBuf>,
+    library_dirs: Vec<PathBuf>,
+    timeout: Duration,
+    jobs: usize,
+}
+
+impl TestSuite {
+    // Tests compile with `builder`'s settings, minus its files.
+    pub fn new(builder: CompileCommandBuilder) -> Self {
+        return Self {
+            builder,
+            patterns: Vec::new(),
+            link: Box::new(|link| link),
+            library: None,
+            library_links: Vec::new(),
+            library_dirs: Vec::new(),
+            timeout: Duration::from_secs(60),
+            jobs: std::thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+        };
+    }
+
+    // Every C source below `dir`.
+    pub fn dir(self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref().display().to_string().replace('\\', "/");
+        return self.pattern(format!("{}/**/*", dir.trim_end_matches('/')));
+    }
+
+    // Sources matching `pattern` relative to the working directory, e.g. `tests/test_*.c`.
+    pub fn pattern(mut self, pattern: impl ToString) -> Self {
+        self.patterns.push(pattern.to_string());
+        return self;
+    }
+
+    // The library under test, built beforehand; its own dependencies come along.
+    pub fn library(mut self, library: &LinkerCommand) -> Self {
+        self.library = Some(library.artifact_path());
+        self.library_links = library.links().to_vec();
+        self.library_dirs = library.link_dirs().to_vec();
+        return self;
+    }
+
+    // Configures each test's link, e.g. the linker or extra libraries.
+    pub fn link(
+        mut self,
+        link: impl for<'a> Fn(LinkerCommandBuilder<'a>) -> LinkerCommandBuilder<'a> + 'static,
+    ) -> Self {
+        self.link = Box::new(link);
+        return self;
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        return self;
+    }
+
+    // Test sources, relative to the working directory.
+    pub fn discover(&self) -> Vec<PathBuf> {
+        let root = self.builder.get_working_dir();
+        let mut files = Vec::new();
+        for pattern in &self.patterns {
+            // Only the part before the first wildcard needs walking.
+            let mut found = Vec::new();
+            walk(&root.join(literal_prefix(pattern)), &mut found);
+            for file in found {
+                let Ok(relative) = file.strip_prefix(root) else {
+                    continue;
+                };
+                let relative_str = relative.display().to_string().replace('\\', "/");
+                if matches(pattern, &relative_str)
+                    && is_source(relative)
+                    && !files.contains(&relative.to_path_buf())
+                {
+                    files.push(relative.to_path_buf());
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn out_dir(&self) -> PathBuf {
+        return self.builder.get_out_dir().join("tests");
+    }
+
+    // `tests/math/add.c` becomes `math_add`, which keeps names unique across directories.
+    fn test_name(&self, src: &Path) -> String {
+        let path = src.display().to_string().replace('\\', "/");
+        let base = self
+            .patterns
+            .iter()
+            .find(|pattern| matches(pattern, &path))
+            .map(|pattern| literal_prefix(pattern));
+        let relative = base
+            .and_then(|base| src.strip_prefix(base).ok())
+            .unwrap_or(src);
+        return relative
+            .with_extension("")
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("_");
+    }
+
+    // Compiles and links one test, returning how to launch it.
+    fn build(&self, src: &Path, name: &str) -> Option<Invocation> {
+        let mut command = self
+            .builder
+            .clone()
+            .out_dir(self.out_dir())
+            .set_files([CompilationFile::new(src)])
+            .build();
+        if let Status::Aborted = command.run() {
+            return None;
+        }
+        let mut link = LinkerCommand::builder(&mut command).name(name);
+        if let Some(library) = &self.library {
+            link = link
+                .link_file(library)
+                .links(self.library_links.iter())
+                .link_dirs(self.library_dirs.iter());
+        }
+        let mut link = (self.link)(link.bin_type(BinType::Binary)).build();
+        if let Status::Aborted = link.run() {
+            return None;
+        }
+        return Some(link.run_invocation());
+    }
+
+    fn execute(invocation: &Invocation, timeout: Duration) -> (Outcome, String, String) {
+        event!(Level::DEBUG, "executing: {}", invocation);
+        let mut cmd = invocation.to_command();
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => return (Outcome::Failed(e.to_string()), String::new(), String::new()),
+        };
+        // Drained while the test runs, so a chatty test can't fill the pipe and stall.
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            let out = Arc::new(Mutex::new(Vec::new()));
+            let sink = Arc::clone(&out);
+            let handle = std::thread::spawn(move || {
+                let Some(mut pipe) = pipe else {
+                    return;
+                };
+                let mut buf = [0; 4096];
+                while let Ok(n @ 1..) = pipe.read(&mut buf) {
+                    if let Ok(mut sink) = sink.lock() {
+                        sink.extend_from_slice(&buf[..n]);
+                    }
+                }
+            });
+            (handle, out)
+        };
+        let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+        let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+        let start = Instant::now();
+        let outcome = loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => break Outcome::Passed,
+                Ok(Some(status)) => break Outcome::Failed(status.to_string()),
+                Ok(None) if start.elapsed() >= timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break Outcome::TimedOut;
+                }
+                Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+                Err(e) => break Outcome::Failed(e.to_string()),
+            }
+        };
+        // Processes the test started can hold the pipes open after it's gone, so the
+        // output is only waited for until the deadline (or briefly, once it passed).
+        let deadline = (start + timeout).max(Instant::now() + Duration::from_millis(100));
+        while !(stdout.0.is_finished() && stderr.0.is_finished()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let text = |out: &Mutex<Vec<u8>>| {
+            out.lock()
+                .map(|out| String::from_utf8_lossy(&out).into_owned())
+                .unwrap_or_default()
+        };
+        let stdout = text(&stdout.1);
+        let stderr = text(&stderr.1);
+        return (outcome, stdout, stderr);
+    }
+
+    fn report(result: &TestResult) {
+        let seconds = result.duration.as_secs_f64();
+        match &result.outcome {
+            Outcome::Passed => println!("[PASS]: {} ({:.2}s)", result.name, seconds),
+            Outcome::Failed(status) => {
+                println!("[FAIL]: {} ({}, {:.2}s)", result.name, status, seconds)
+            }
+            Outcome::TimedOut => {
+                println!("[FAIL]: {} (timed out after {:.2}s)", result.name, seconds)
+            }
+            Outcome::BuildFailed => println!("[FAIL]: {} (build failed)", result.name),
+        }
+        if !result.passed() {
+            for line in result.stdout.lines().chain(result.stderr.lines()) {
+                println!("    {}", line);
+            }
+        }
+    }
+
+    // Builds every test one after another, then runs them `jobs` at a time.
+    pub fn run(&self) -> TestReport {
+        let start = Instant::now();
+        let root = self.builder.get_working_dir().to_path_buf();
+        let mut tests: Vec<(PathBuf, String, Result<Invocation, Outcome>)> = Vec::new();
+        for src in self.discover() {
+            let name = self.test_name(&src);
+            // `tests/a_b.c` and `tests/a/b.c` would share a name and a binary.
+            if let Some((other, _, _)) = tests.iter().find(|(_, other, _)| *other == name) {
+                let clash = format!("test name `{}` is taken by `{}`", name, other.display());
+                tests.push((src, name, Err(Outcome::Failed(clash))));
+                continue;
+            }
+            let invocation = self.build(&src, &name).ok_or(Outcome::BuildFailed);
+            tests.push((src, name, invocation));
+        }
+
+        let timeout = self.timeout;
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; tests.len()]);
+        std::thread::scope(|scope| {
+            for _ in 0..self.jobs.min(tests.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((src, name, invocation)) = tests.get(i) else {
+                        break;
+                    };
+                    let begin = Instant::now();
+                    let (outcome, stdout, stderr) = match invocation {
+                        Ok(invocation) => Self::execute(invocation, timeout),
+                        Err(outcome) => (outcome.clone(), String::new(), String::new()),
+                    };
+                    let result = TestResult {
+                        name: name.clone(),
+                        src: src.clone(),
+                        outcome,
+                        duration: begin.elapsed(),
+                        stdout,
+                        stderr,
+                    };
+                    Self::report(&result);
+                    if let Ok(mut results) = results.lock() {
+                        results[i] = Some(result);
+                    }
+                });
+            }
+        });
+
+        let report = TestReport {
+            results: results
+                .into_inner()
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .collect(),
+            duration: start.elapsed(),
+        };
+        let junit = root.join(self.out_dir()).join("junit.xml");
+        if let Err(e) = std::fs::create_dir_all(root.join(self.out_dir()))
+            .and_then(|_| std::fs::write(&junit, report.junit()))
+        {
+            event!(Level::WARN, "failed to write junit report: `{}`", e);
+        }
+        println!("[Tests]: {}", report);
+        return report;
+    }
+}
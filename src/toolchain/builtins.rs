@@ -0,0 +1,162 @@
+use super::probe::null_device;
+use super::{Family, ToolchainInfo};
+use crate::compiler::Lang;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{event, Level};
+
+// The compiler's implicit include directories and predefined macros.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Builtins {
+    include_dirs: Vec<PathBuf>,
+    macros: Vec<(String, String)>,
+}
+
+fn lang_flag(lang: &Lang) -> &'static str {
+    match lang {
+        Lang::C => "c",
+        Lang::Cpp => "c++",
+    }
+}
+
+fn is_predefined(name: &str) -> bool {
+    const PREFIXES: [&str; 4] = ["__STDC", "__GNUC", "__clang", "__apple_build_version"];
+    matches!(
+        name,
+        "__VERSION__" | "__llvm__" | "__GNUG__" | "__cplusplus"
+    ) || PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+impl Builtins {
+    pub fn new(include_dirs: Vec<PathBuf>, macros: Vec<(String, String)>) -> Self {
+        Self {
+            include_dirs,
+            macros,
+        }
+    }
+
+    pub fn query(info: &ToolchainInfo, lang: &Lang, cache_dir: impl AsRef<Path>) -> Option<Self> {
+        if info.family() == Family::Msvc {
+            return None;
+        }
+        let cache = cache_dir.as_ref().join(format!(
+            "builtins-{}-{}.cache",
+            info.fingerprint(),
+            lang_flag(lang)
+        ));
+        if let Ok(content) = std::fs::read_to_string(&cache) {
+            return Some(Self::from_cache(&content));
+        }
+
+        let verbose = Command::new(info.program())
+            .args(["-E", "-v", "-x", lang_flag(lang), null_device()])
+            .output()
+            .ok()?;
+        let macros = Command::new(info.program())
+            .args(["-dM", "-E", "-x", lang_flag(lang), null_device()])
+            .output()
+            .ok()?;
+        let builtins = Self {
+            include_dirs: Self::parse_include_dirs(&String::from_utf8_lossy(&verbose.stderr)),
+            macros: Self::parse_macros(&String::from_utf8_lossy(&macros.stdout)),
+        };
+
+        if let Err(e) = std::fs::create_dir_all(cache_dir.as_ref())
+            .and_then(|_| std::fs::write(&cache, builtins.to_cache()))
+        {
+            event!(Level::WARN, "failed to write builtins cache: `{}`", e);
+        }
+        return Some(builtins);
+    }
+
+    // Reads the search list printed by `-E -v`, between the
+    // `#include <...> search starts here:` and `End of search list.` markers.
+    pub fn parse_include_dirs(output: &str) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        let mut in_list = false;
+        for line in output.lines() {
+            // Directories above this marker only serve `#include "..."`.
+            if line.starts_with("#include <...> search starts here:") {
+                in_list = true;
+                continue;
+            }
+            if line.starts_with("End of search list.") {
+                break;
+            }
+            if in_list {
+                // macOS marks framework directories with a trailing annotation.
+                let dir = line.trim().trim_end_matches(" (framework directory)");
+                dirs.push(PathBuf::from(dir));
+            }
+        }
+        dirs
+    }
+
+    pub fn parse_macros(output: &str) -> Vec<(String, String)> {
+        return output
+            .lines()
+            .filter_map(|line| line.strip_prefix("#define "))
+            .map(|line| match line.split_once(' ') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => (line.to_string(), String::new()),
+            })
+            .collect();
+    }
+
+    pub fn include_dirs(&self) -> &[PathBuf] {
+        &self.include_dirs
+    }
+
+    pub fn macros(&self) -> &[(String, String)] {
+        &self.macros
+    }
+
+    pub fn macro_value(&self, name: &str) -> Option<&str> {
+        return self
+            .macros
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str());
+    }
+
+    // Flags that make the builtins explicit, for tools that don't query the compiler themselves.
+    // The tool's own frontend predefines the standard and compiler-identity macros, so
+    // redefining those would only produce warnings.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for dir in &self.include_dirs {
+            args.push("-isystem".into());
+            args.push(dir.display().to_string());
+        }
+        for (name, value) in &self.macros {
+            if !is_predefined(name) {
+                args.push(format!("-D{}={}", name, value));
+            }
+        }
+        args
+    }
+
+    fn to_cache(&self) -> String {
+        let mut content = String::new();
+        for dir in &self.include_dirs {
+            content.push_str(&format!("include={}\n", dir.display()));
+        }
+        for (name, value) in &self.macros {
+            content.push_str(&format!("define={} {}\n", name, value));
+        }
+        content
+    }
+
+    fn from_cache(content: &str) -> Self {
+        let mut builtins = Self::default();
+        for line in content.lines() {
+            if let Some(dir) = line.strip_prefix("include=") {
+                builtins.include_dirs.push(dir.into());
+            } else if let Some(def) = line.strip_prefix("define=") {
+                let (name, value) = def.split_once(' ').unwrap_or((def, ""));
+                builtins.macros.push((name.into(), value.into()));
+            }
+        }
+        builtins
+    }
+}
@@ -0,0 +1,568 @@
+use crate::compiler::{DebugInfo, Lang, Lto, OptimizationLevel, Sanitizer, Std, Target};
+use crate::coverage::CoverageFormat;
+use crate::linker::BinType;
+use crate::pgo::ProfileFormat;
+use std::path::{Path, PathBuf};
+
+mod builtins;
+mod probe;
+
+pub use builtins::Builtins;
+pub use probe::{find_in_path, Family, Probe, ToolchainInfo, Version};
+
+fn fsanitize(sanitizers: &[Sanitizer]) -> String {
+    let names = sanitizers
+        .iter()
+        .map(|sanitizer| sanitizer.name())
+        .collect::<Vec<_>>();
+    return format!("-fsanitize={}", names.join(","));
+}
+
+// `<name>.dll.lib`, so a DLL's import library never overwrites a static `<name>.lib`.
+pub(crate) fn import_lib(dll: &Path) -> PathBuf {
+    let mut lib = dll.as_os_str().to_owned();
+    lib.push(".lib");
+    PathBuf::from(lib)
+}
+
+// Flag translation for every step cbuild drives. The provided methods follow the
+// GCC driver syntax, so GCC-compatible compilers (tcc, zig cc, icx, ...) usually
+// only need to name their programs.
+pub trait Toolchain: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compiler(&self, lang: &Lang) -> &str;
+
+    fn archiver(&self) -> &str {
+        "ar"
+    }
+
+    // Archiver that keeps the symbol index usable for LTO objects.
+    fn lto_archiver(&self) -> &str {
+        self.archiver()
+    }
+
+    fn linker(&self, _bin_type: &BinType) -> &str {
+        self.compiler(&Lang::C)
+    }
+
+    fn object_ext(&self) -> &str {
+        "o"
+    }
+
+    fn compile_preamble(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn src_file(&self, src: &Path, out: &Path) -> Vec<String> {
+        return Vec::from([
+            "-c".into(),
+            src.display().to_string(),
+            "-o".into(),
+            out.display().to_string(),
+        ]);
+    }
+
+    // Preprocesses `src` to stdout.
+    fn preprocess(&self, src: &Path) -> Vec<String> {
+        return Vec::from(["-E".into(), src.display().to_string()]);
+    }
+
+    fn opt_level(&self, level: &OptimizationLevel) -> Option<String> {
+        let flag = match level {
+            OptimizationLevel::O3 => "-O3",
+            OptimizationLevel::O2 => "-O2",
+            OptimizationLevel::O1 => "-O1",
+            OptimizationLevel::O0 => "-O0",
+            OptimizationLevel::Fast => "-Ofast",
+            OptimizationLevel::Small => "-Os",
+        };
+        return Some(flag.into());
+    }
+
+    fn debug(&self, info: &DebugInfo) -> Option<String> {
+        match info {
+            DebugInfo::Default => return Some("-g".into()),
+            DebugInfo::Level(level) => return Some(format!("-g{}", level)),
+        }
+    }
+
+    fn lang_std(&self, std: &Std) -> Option<String> {
+        match std {
+            Std::C99 => return Some("-std=c99".into()),
+            Std::C11 => return Some("-std=c11".into()),
+        }
+    }
+
+    fn target(&self, _target: &Target) -> Option<String> {
+        None
+    }
+
+    fn warnings_as_errors(&self) -> String {
+        "-Werror".into()
+    }
+
+    // Position-independent code, for objects that may end up in shared objects or PIEs.
+    fn pic(&self) -> Option<String> {
+        return Some("-fPIC".into());
+    }
+
+    // Emits objects for link-time optimization; `None` if the toolchain can't.
+    fn lto(&self, lto: &Lto) -> Option<String> {
+        match lto {
+            Lto::Full => return Some("-flto".into()),
+            Lto::Thin => return Some("-flto=thin".into()),
+        }
+    }
+
+    // How the toolchain instruments code for coverage; `None` if it can't.
+    fn coverage(&self) -> Option<CoverageFormat> {
+        return Some(CoverageFormat::Gcov);
+    }
+
+    // How the toolchain records profiles for profile-guided optimization.
+    fn pgo(&self) -> Option<ProfileFormat> {
+        return Some(ProfileFormat::Gcda);
+    }
+
+    // Instruments code for `sanitizers`; `None` if one of them isn't available.
+    // Frame pointers keep the runtime's stack traces usable at any optimization level.
+    fn sanitize(&self, sanitizers: &[Sanitizer]) -> Option<Vec<String>> {
+        return Some(Vec::from([
+            fsanitize(sanitizers),
+            "-fno-omit-frame-pointer".into(),
+        ]));
+    }
+
+    fn include(&self, dir: &Path) -> Vec<String> {
+        return Vec::from(["-I".into(), dir.display().to_string()]);
+    }
+
+    fn definition(&self, def: &str) -> String {
+        return format!("-D{}", def);
+    }
+
+    // Makes the compiler report the headers it read, for incremental rebuilds.
+    fn depfile(&self, dep: &Path) -> Vec<String> {
+        return Vec::from(["-MMD".into(), "-MF".into(), dep.display().to_string()]);
+    }
+
+    // Ninja's `deps` mode for the output of `depfile`.
+    fn deps_format(&self) -> &str {
+        "gcc"
+    }
+
+    // Whether the compiler driver assembles GAS-syntax `.s`/`.S` sources.
+    fn assembles_gas(&self) -> bool {
+        true
+    }
+
+    fn pch_ext(&self) -> &str {
+        "gch"
+    }
+
+    fn pch_create(&self, header: &Path, pch: &Path, lang: &Lang) -> Vec<String> {
+        let kind = match lang {
+            Lang::C => "c-header",
+            Lang::Cpp => "c++-header",
+        };
+        return Vec::from([
+            "-x".into(),
+            kind.into(),
+            header.display().to_string(),
+            "-o".into(),
+            pch.display().to_string(),
+        ]);
+    }
+
+    // GCC picks up `<header>.gch` when asked to `-include <header>`.
+    fn pch_use(&self, _header: &Path, pch: &Path) -> Vec<String> {
+        return Vec::from([
+            "-include".into(),
+            pch.with_extension("").display().to_string(),
+        ]);
+    }
+
+    // Object emitted next to the PCH that has to be linked in, if the toolchain produces one.
+    fn pch_object(&self, _pch: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    fn archive(&self, out: &Path, objects: &[PathBuf]) -> Vec<String> {
+        let mut args = Vec::from(["rcs".into(), out.display().to_string()]);
+        args.extend(objects.iter().map(|obj| obj.display().to_string()));
+        args
+    }
+
+    fn link_preamble(&self, bin_type: &BinType) -> Vec<String> {
+        match bin_type {
+            BinType::DynamicLib => return Vec::from(["-shared".into()]),
+            _ => return Vec::new(),
+        }
+    }
+
+    fn link_out(&self, out: &Path) -> Vec<String> {
+        return Vec::from(["-o".into(), out.display().to_string()]);
+    }
+
+    fn link_dir(&self, dir: &Path) -> Vec<String> {
+        return Vec::from(["-L".into(), dir.display().to_string()]);
+    }
+
+    fn link_lib(&self, lib: &Path) -> Vec<String> {
+        return Vec::from(["-l".into(), lib.display().to_string()]);
+    }
+
+    // Runs the optimizer over objects built with `lto`.
+    fn lto_link(&self, lto: &Lto) -> Option<String> {
+        return self.lto(lto);
+    }
+
+    // Links in the runtimes for objects built with `sanitize`.
+    fn sanitize_link(&self, sanitizers: &[Sanitizer]) -> Option<Vec<String>> {
+        return Some(Vec::from([fsanitize(sanitizers)]));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Gcc;
+
+impl Toolchain for Gcc {
+    fn name(&self) -> &str {
+        "gcc"
+    }
+
+    fn compiler(&self, lang: &Lang) -> &str {
+        match lang {
+            Lang::C => "gcc",
+            Lang::Cpp => "g++",
+        }
+    }
+
+    // `ar` can't index GIMPLE objects without the LTO plugin `gcc-ar` loads.
+    fn lto_archiver(&self) -> &str {
+        "gcc-ar"
+    }
+
+    // GCC has no thin LTO; its regular LTO already partitions the link-time work.
+    fn lto(&self, _lto: &Lto) -> Option<String> {
+        return Some("-flto".into());
+    }
+
+    fn sanitize(&self, sanitizers: &[Sanitizer]) -> Option<Vec<String>> {
+        if sanitizers.contains(&Sanitizer::Memory) {
+            return None;
+        }
+        return Some(Vec::from([
+            fsanitize(sanitizers),
+            "-fno-omit-frame-pointer".into(),
+        ]));
+    }
+
+    fn sanitize_link(&self, sanitizers: &[Sanitizer]) -> Option<Vec<String>> {
+        if sanitizers.contains(&Sanitizer::Memory) {
+            return None;
+        }
+        return Some(Vec::from([fsanitize(sanitizers)]));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Clang;
+
+impl Toolchain for Clang {
+    fn name(&self) -> &str {
+        "clang"
+    }
+
+    fn compiler(&self, lang: &Lang) -> &str {
+        match lang {
+            Lang::C => "clang",
+            Lang::Cpp => "clang++",
+        }
+    }
+
+    fn archiver(&self) -> &str {
+        "llvm-ar"
+    }
+
+    fn pch_ext(&self) -> &str {
+        "pch"
+    }
+
+    fn pch_use(&self, _header: &Path, pch: &Path) -> Vec<String> {
+        return Vec::from(["-include-pch".into(), pch.display().to_string()]);
+    }
+
+    fn coverage(&self) -> Option<CoverageFormat> {
+        return Some(CoverageFormat::Llvm);
+    }
+
+    fn pgo(&self) -> Option<ProfileFormat> {
+        return Some(ProfileFormat::Llvm);
+    }
+
+    // Clang wants a full triple: the target's architecture with the host's OS and ABI.
+    fn target(&self, target: &Target) -> Option<String> {
+        let arch = match target {
+            Target::X86_64 => "x86_64",
+        };
+        let triple = match std::env::consts::OS {
+            "linux" => format!("{}-pc-linux-gnu", arch),
+            "macos" => format!("{}-apple-darwin", arch),
+            "windows" => format!("{}-pc-windows-msvc", arch),
+            os => format!("{}-unknown-{}", arch, os),
+        };
+        return Some(format!("--target={}", triple));
+    }
+}
+
+// Bare GNU-style linkers (`ld`, `lld`) driven directly instead of through a compiler driver.
+#[derive(Debug, Clone)]
+pub struct Ld {
+    program: &'static str,
+    driver: &'static dyn Toolchain,
+}
+
+impl Ld {
+    pub const fn new(program: &'static str) -> Self {
+        return Self {
+            program,
+            driver: &Gcc,
+        };
+    }
+
+    // The toolchain that compiles the sources a command links with this linker.
+    pub const fn driver(mut self, driver: &'static dyn Toolchain) -> Self {
+        self.driver = driver;
+        return self;
+    }
+}
+
+impl Toolchain for Ld {
+    fn name(&self) -> &str {
+        self.program
+    }
+
+    // A bare linker can't compile; a command given this toolchain compiles with its
+    // driver and links with the linker itself.
+    fn compiler(&self, lang: &Lang) -> &str {
+        self.driver.compiler(lang)
+    }
+
+    fn linker(&self, _bin_type: &BinType) -> &str {
+        self.program
+    }
+
+    // The profiling runtime is only found through a compiler driver.
+    fn coverage(&self) -> Option<CoverageFormat> {
+        None
+    }
+
+    fn pgo(&self) -> Option<ProfileFormat> {
+        None
+    }
+
+    // The LTO plugins are only loaded through a compiler driver.
+    fn lto_link(&self, _lto: &Lto) -> Option<String> {
+        None
+    }
+
+    // The runtimes are only found through a compiler driver.
+    fn sanitize_link(&self, _sanitizers: &[Sanitizer]) -> Option<Vec<String>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Msvc;
+
+impl Toolchain for Msvc {
+    fn name(&self) -> &str {
+        "msvc"
+    }
+
+    fn compiler(&self, _lang: &Lang) -> &str {
+        "cl"
+    }
+
+    fn archiver(&self) -> &str {
+        "lib.exe"
+    }
+
+    fn linker(&self, _bin_type: &BinType) -> &str {
+        "link.exe"
+    }
+
+    fn object_ext(&self) -> &str {
+        "obj"
+    }
+
+    fn compile_preamble(&self) -> Vec<String> {
+        return Vec::from(["/nologo".into()]);
+    }
+
+    fn src_file(&self, src: &Path, out: &Path) -> Vec<String> {
+        return Vec::from([
+            "/c".into(),
+            src.display().to_string(),
+            format!("/Fo{}", out.display()),
+        ]);
+    }
+
+    fn preprocess(&self, src: &Path) -> Vec<String> {
+        return Vec::from(["/E".into(), src.display().to_string()]);
+    }
+
+    fn opt_level(&self, level: &OptimizationLevel) -> Option<String> {
+        let flag = match level {
+            OptimizationLevel::O3 => "/O2",
+            OptimizationLevel::O2 => "/O2",
+            OptimizationLevel::O1 => "/O1",
+            OptimizationLevel::O0 => "/Od",
+            OptimizationLevel::Fast => "/Ot",
+            OptimizationLevel::Small => "/Os",
+        };
+        return Some(flag.into());
+    }
+
+    // Debug info goes into the objects, so parallel compiles don't contend for one PDB.
+    fn debug(&self, _info: &DebugInfo) -> Option<String> {
+        return Some("/Z7".into());
+    }
+
+    fn lang_std(&self, std: &Std) -> Option<String> {
+        match std {
+            Std::C99 => return None,
+            Std::C11 => return Some("/std:c11".into()),
+        }
+    }
+
+    fn warnings_as_errors(&self) -> String {
+        "/WX".into()
+    }
+
+    fn pic(&self) -> Option<String> {
+        None
+    }
+
+    // Whole program optimization; MSVC has no thin variant.
+    fn lto(&self, _lto: &Lto) -> Option<String> {
+        return Some("/GL".into());
+    }
+
+    fn coverage(&self) -> Option<CoverageFormat> {
+        None
+    }
+
+    // MSVC's PGO goes through `/GENPROFILE` and `pgomgr`, which cbuild doesn't drive.
+    fn pgo(&self) -> Option<ProfileFormat> {
+        None
+    }
+
+    // MSVC only ships AddressSanitizer.
+    fn sanitize(&self, sanitizers: &[Sanitizer]) -> Option<Vec<String>> {
+        if sanitizers
+            .iter()
+            .any(|sanitizer| *sanitizer != Sanitizer::Address)
+        {
+            return None;
+        }
+        return Some(Vec::from(["/fsanitize=address".into()]));
+    }
+
+    fn include(&self, dir: &Path) -> Vec<String> {
+        return Vec::from(["/I".into(), dir.display().to_string()]);
+    }
+
+    fn definition(&self, def: &str) -> String {
+        return format!("/D{}", def);
+    }
+
+    fn depfile(&self, _dep: &Path) -> Vec<String> {
+        return Vec::from(["/showIncludes".into()]);
+    }
+
+    fn deps_format(&self) -> &str {
+        "msvc"
+    }
+
+    fn assembles_gas(&self) -> bool {
+        false
+    }
+
+    fn pch_ext(&self) -> &str {
+        "pch"
+    }
+
+    // The header itself is compiled as the /Yc translation unit.
+    fn pch_create(&self, header: &Path, pch: &Path, lang: &Lang) -> Vec<String> {
+        let kind = match lang {
+            Lang::C => "/Tc",
+            Lang::Cpp => "/Tp",
+        };
+        return Vec::from([
+            "/c".into(),
+            format!("{}{}", kind, header.display()),
+            "/Yc".into(),
+            format!("/Fp{}", pch.display()),
+            format!("/Fo{}", pch.with_extension("obj").display()),
+        ]);
+    }
+
+    fn pch_use(&self, header: &Path, pch: &Path) -> Vec<String> {
+        return Vec::from([
+            format!("/Yu{}", header.display()),
+            format!("/FI{}", header.display()),
+            format!("/Fp{}", pch.display()),
+        ]);
+    }
+
+    fn pch_object(&self, pch: &Path) -> Option<PathBuf> {
+        return Some(pch.with_extension("obj"));
+    }
+
+    fn archive(&self, out: &Path, objects: &[PathBuf]) -> Vec<String> {
+        let mut args = Vec::from(["/nologo".into(), format!("/OUT:{}", out.display())]);
+        args.extend(objects.iter().map(|obj| obj.display().to_string()));
+        args
+    }
+
+    fn link_preamble(&self, bin_type: &BinType) -> Vec<String> {
+        match bin_type {
+            BinType::DynamicLib => return Vec::from(["/nologo".into(), "/DLL".into()]),
+            _ => return Vec::from(["/nologo".into()]),
+        }
+    }
+
+    fn link_out(&self, out: &Path) -> Vec<String> {
+        let mut args = Vec::from([format!("/OUT:{}", out.display())]);
+        if out.extension().is_some_and(|ext| ext == "dll") {
+            args.push(format!("/IMPLIB:{}", import_lib(out).display()));
+        }
+        return args;
+    }
+
+    fn link_dir(&self, dir: &Path) -> Vec<String> {
+        return Vec::from([format!("/LIBPATH:{}", dir.display())]);
+    }
+
+    fn link_lib(&self, lib: &Path) -> Vec<String> {
+        return Vec::from([lib.display().to_string()]);
+    }
+
+    fn lto_link(&self, _lto: &Lto) -> Option<String> {
+        return Some("/LTCG".into());
+    }
+
+    // `cl` records the runtime libraries in the objects for `link.exe` to pick up.
+    fn sanitize_link(&self, sanitizers: &[Sanitizer]) -> Option<Vec<String>> {
+        if sanitizers
+            .iter()
+            .any(|sanitizer| *sanitizer != Sanitizer::Address)
+        {
+            return None;
+        }
+        return Some(Vec::new());
+    }
+}
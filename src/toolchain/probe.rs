@@ -0,0 +1,354 @@
+use super::{Clang, Gcc, Msvc, Toolchain};
+use crate::compiler::{Lang, Std};
+use crate::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use tracing::{event, Level};
+
+const CACHE_FILE: &str = "toolchain.cache";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Gcc,
+    Clang,
+    Msvc,
+    Unknown,
+}
+
+impl Family {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Family::Gcc => "gcc",
+            Family::Clang => "clang",
+            Family::Msvc => "msvc",
+            Family::Unknown => "unknown",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "gcc" => Family::Gcc,
+            "clang" => Family::Clang,
+            "msvc" => Family::Msvc,
+            _ => Family::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        return Self {
+            major,
+            minor,
+            patch,
+        };
+    }
+
+    // Parses the first `x.y[.z]` token found in `text`.
+    pub fn find(text: &str) -> Option<Self> {
+        for word in text.split(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+            let mut parts = word.split('.');
+            let major = parts.next().and_then(|part| part.parse().ok());
+            let minor = parts.next().and_then(|part| part.parse().ok());
+            if let (Some(major), Some(minor)) = (major, minor) {
+                let patch = parts.next().and_then(|part| part.parse().ok());
+                return Some(Self::new(major, minor, patch.unwrap_or(0)));
+            }
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolchainInfo {
+    program: PathBuf,
+    // The C++ driver next to `program`, e.g. `g++` for `gcc`.
+    cxx: String,
+    family: Family,
+    version: Version,
+    target: String,
+}
+
+pub(super) fn null_device() -> &'static str {
+    if cfg!(target_os = "windows") {
+        return "NUL";
+    }
+    return "/dev/null";
+}
+
+fn output_of(program: &Path, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    let mut text = String::from_utf8_lossy(&out.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&out.stderr));
+    return Some(text);
+}
+
+fn macro_value(macros: &str, name: &str) -> Option<u32> {
+    let prefix = format!("#define {} ", name);
+    return macros
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .and_then(|value| value.trim().parse().ok());
+}
+
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    for dir in std::env::split_paths(&path) {
+        let candidate = dir.join(name);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if cfg!(target_os = "windows") {
+            let candidate = candidate.with_extension("exe");
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+impl ToolchainInfo {
+    pub fn new(
+        program: impl Into<PathBuf>,
+        family: Family,
+        version: Version,
+        target: impl ToString,
+    ) -> Self {
+        let program = program.into();
+        Self {
+            cxx: cxx_for(&program.display().to_string()),
+            program,
+            family,
+            version,
+            target: target.to_string(),
+        }
+    }
+
+    pub fn probe(program: impl Into<PathBuf>) -> Option<Self> {
+        let program = program.into();
+        let version_text = output_of(&program, &["--version"])?;
+        if version_text.contains("Microsoft") {
+            let version = Version::find(&version_text).unwrap_or_default();
+            return Some(Self::new(program, Family::Msvc, version, ""));
+        }
+
+        let macros =
+            output_of(&program, &["-dM", "-E", "-x", "c", null_device()]).unwrap_or_default();
+        let (family, version) = if let Some(major) = macro_value(&macros, "__clang_major__") {
+            let minor = macro_value(&macros, "__clang_minor__").unwrap_or(0);
+            let patch = macro_value(&macros, "__clang_patchlevel__").unwrap_or(0);
+            (Family::Clang, Version::new(major, minor, patch))
+        } else if let Some(major) = macro_value(&macros, "__GNUC__") {
+            let minor = macro_value(&macros, "__GNUC_MINOR__").unwrap_or(0);
+            let patch = macro_value(&macros, "__GNUC_PATCHLEVEL__").unwrap_or(0);
+            (Family::Gcc, Version::new(major, minor, patch))
+        } else {
+            (
+                Family::Unknown,
+                Version::find(&version_text).unwrap_or_default(),
+            )
+        };
+        let target = output_of(&program, &["-dumpmachine"])
+            .map(|target| target.trim().to_string())
+            .unwrap_or_default();
+
+        return Some(Self::new(program, family, version, target));
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    // The detected driver for `lang`; the toolchain's own names stay as they are.
+    pub fn compiler(&self, lang: &Lang) -> &str {
+        match lang {
+            Lang::C => self.program.to_str().unwrap_or_default(),
+            Lang::Cpp => &self.cxx,
+        }
+    }
+
+    pub fn family(&self) -> Family {
+        self.family
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn supports_std(&self, std: &Std) -> bool {
+        let version = self.version;
+        match (self.family, std) {
+            (Family::Gcc, Std::C99) => version >= Version::new(4, 0, 0),
+            (Family::Gcc, Std::C11) => version >= Version::new(4, 7, 0),
+            (Family::Clang, Std::C99) => true,
+            (Family::Clang, Std::C11) => version >= Version::new(3, 1, 0),
+            (Family::Msvc, Std::C99) => false,
+            (Family::Msvc, Std::C11) => version >= Version::new(19, 28, 0),
+            (Family::Unknown, _) => true,
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Hasher::new();
+        hasher.field(self.program.display().to_string().as_bytes());
+        hasher.field(self.family.as_str().as_bytes());
+        hasher.field(self.version.to_string().as_bytes());
+        hasher.field(self.target.as_bytes());
+        return hasher.finish();
+    }
+
+    // The built-in toolchain for the family; `CompileCommand` swaps in `compiler()`
+    // wherever the toolchain names its driver.
+    pub fn toolchain(&self) -> Arc<dyn Toolchain> {
+        match self.family {
+            Family::Clang => return Arc::new(Clang),
+            Family::Msvc => return Arc::new(Msvc),
+            Family::Gcc | Family::Unknown => return Arc::new(Gcc),
+        }
+    }
+
+    // `available` is what the probe saw on `PATH`, so a changed `PATH` or candidate
+    // list invalidates the entry along with a changed binary.
+    fn to_cache(&self, available: &str) -> String {
+        return format!(
+            "available={}\nprogram={}\nmtime={}\nfamily={}\nversion={}\ntarget={}\n",
+            available,
+            self.program.display(),
+            modified_secs(&self.program),
+            self.family.as_str(),
+            self.version,
+            self.target
+        );
+    }
+
+    fn from_cache(content: &str, available: &str) -> Option<Self> {
+        let value = |key: &str| {
+            content
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        };
+        if value("available")? != available {
+            return None;
+        }
+        let program = PathBuf::from(value("program")?);
+        if value("mtime")?.parse::<u64>().ok()? != modified_secs(&program) {
+            return None;
+        }
+        return Some(Self::new(
+            program,
+            Family::parse(value("family")?),
+            Version::find(value("version")?)?,
+            value("target")?,
+        ));
+    }
+}
+
+fn cxx_for(program: &str) -> String {
+    for (c, cxx) in [("clang", "clang++"), ("gcc", "g++"), ("cc", "c++")] {
+        if let Some(prefix) = program.strip_suffix(c) {
+            return format!("{}{}", prefix, cxx);
+        }
+    }
+    return program.to_string();
+}
+
+fn modified_secs(program: &Path) -> u64 {
+    return program
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+}
+
+pub struct Probe {
+    cache_dir: PathBuf,
+    candidates: Vec<String>,
+}
+
+impl Probe {
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        #[cfg(target_os = "windows")]
+        let candidates = ["cl", "clang", "gcc"];
+        #[cfg(target_os = "macos")]
+        let candidates = ["clang", "cc", "gcc"];
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        let candidates = ["cc", "gcc", "clang"];
+        return Self {
+            cache_dir: cache_dir.into(),
+            candidates: candidates.iter().map(|c| c.to_string()).collect(),
+        };
+    }
+
+    pub fn candidate(mut self, program: impl ToString) -> Self {
+        self.candidates.insert(0, program.to_string());
+        return self;
+    }
+
+    pub fn set_candidates(mut self, programs: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.candidates = programs.into_iter().map(|c| c.to_string()).collect();
+        return self;
+    }
+
+    pub fn available(&self) -> Vec<PathBuf> {
+        return self
+            .candidates
+            .iter()
+            .filter_map(|candidate| find_in_path(candidate))
+            .collect();
+    }
+
+    pub fn detect(&self) -> Option<ToolchainInfo> {
+        let cache = self.cache_dir.join(CACHE_FILE);
+        let available = self.available();
+        if available.is_empty() {
+            return None;
+        }
+        let key = available
+            .iter()
+            .map(|program| program.display().to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        if let Ok(content) = std::fs::read_to_string(&cache) {
+            if let Some(info) = ToolchainInfo::from_cache(&content, &key) {
+                return Some(info);
+            }
+        }
+
+        let info = available.iter().find_map(ToolchainInfo::probe)?;
+        event!(
+            Level::INFO,
+            "detected {} {} at `{}`",
+            info.family.as_str(),
+            info.version,
+            info.program.display()
+        );
+        let content = info.to_cache(&key);
+        if let Err(e) =
+            std::fs::create_dir_all(&self.cache_dir).and_then(|_| std::fs::write(&cache, content))
+        {
+            event!(Level::WARN, "failed to write toolchain cache: `{}`", e);
+        }
+        return Some(info);
+    }
+}